@@ -2,11 +2,27 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
+use crate::commands::DEFAULT_PROFILE;
+use crate::deps::Scope;
+use crate::report::MessageFormat;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Require Cup.lock to be up to date instead of updating it
+    #[arg(long, global = true)]
+    pub locked: bool,
+
+    /// Resolve dependencies only from the local cache and ~/.m2/repository
+    #[arg(long, global = true, env = "CUP_OFFLINE", value_parser = clap::builder::BoolishValueParser::new())]
+    pub offline: bool,
+
+    /// How build, run and test report progress and compiler diagnostics
+    #[arg(long, global = true, value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
 }
 
 #[derive(Subcommand)]
@@ -19,7 +35,100 @@ pub enum Commands {
         #[arg(short, long)]
         kotlin: bool,
     },
-    Build {},
-    Run {},
+    Build {
+        /// [profile.<name>] in Cup.toml to build with
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
+    Run {
+        /// [profile.<name>] in Cup.toml to build with
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+
+        /// JVM option, added after [run] jvm_args (repeatable)
+        #[arg(long = "jvm-arg", value_name = "ARG", allow_hyphen_values = true)]
+        jvm_args: Vec<String>,
+
+        /// System property, overrides [run] system_properties (repeatable)
+        #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        system_properties: Vec<(String, String)>,
+
+        /// Environment variable for the program, overrides [run] env (repeatable)
+        #[arg(long, value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        env: Vec<(String, String)>,
+
+        /// Directory to run the program in, overrides [run] working_dir
+        #[arg(long)]
+        working_dir: Option<String>,
+
+        /// Arguments for the program, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Compiles and runs the tests with the JUnit Platform
+    Test {
+        /// [profile.<name>] in Cup.toml to build with
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
     Doc {},
+    /// Adds a Maven dependency to Cup.toml
+    Add {
+        /// group:artifact:version
+        coordinates: String,
+
+        /// key to use in Cup.toml, defaults to the artifact id
+        #[arg(long)]
+        name: Option<String>,
+
+        /// add it to [dev-dependencies]
+        #[arg(long)]
+        dev: bool,
+
+        #[arg(long, value_parser = clap::value_parser!(Scope))]
+        scope: Option<Scope>,
+    },
+    /// Prints the resolved dependency graph
+    Tree {
+        /// show what pulls in this artifact (artifact, group:artifact or group:artifact:version)
+        #[arg(short, long, value_name = "ARTIFACT")]
+        invert: Option<String>,
+    },
+    /// Removes a dependency from Cup.toml
+    Remove {
+        /// name, artifact id or group:artifact of the dependency
+        name: String,
+    },
+    /// Manages the JDKs and Kotlin compilers Cup builds with
+    Toolchain {
+        #[command(subcommand)]
+        command: ToolchainCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ToolchainCommands {
+    /// Unpacks a JDK or Kotlin compiler archive into ~/.cup/toolchains
+    Add {
+        /// .tar.gz, .tar.xz or .zip distribution
+        archive: PathBuf,
+    },
+    /// Lists the JDKs and Kotlin compilers Cup can find
+    List {},
+    /// Sets the JDK used by projects that don't pick one
+    Default {
+        /// e.g. 21 or 21.0.2
+        version: String,
+
+        /// set the default Kotlin compiler instead
+        #[arg(long)]
+        kotlin: bool,
+    },
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got '{value}'")),
+    }
 }
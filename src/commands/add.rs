@@ -0,0 +1,93 @@
+use anyhow::{Context, Result, bail};
+use toml_edit::{DocumentMut, InlineTable, Item, Table, value};
+
+use crate::commands::{config_from_manifest, load_manifest, save_manifest};
+use crate::deps::{Coordinate, ResolveOptions, Scope, resolve_dependencies};
+
+/// Adds a Maven dependency to `Cup.toml`, keeping the rest of the file untouched,
+/// and updates `Cup.lock`.
+pub fn add_dependency(
+    coordinates: String,
+    name: Option<String>,
+    dev: bool,
+    scope: Option<Scope>,
+    options: &ResolveOptions,
+) -> Result<()> {
+    let coordinate: Coordinate = coordinates.parse()?;
+    if dev && scope.is_some_and(|s| s != Scope::Test) {
+        bail!("--dev dependencies are always test-scoped, drop --scope or --dev");
+    }
+    // [dev-dependencies] is where test-scoped dependencies live
+    let dev = dev || scope == Some(Scope::Test);
+
+    let name = name.unwrap_or_else(|| coordinate.artifact.clone());
+    let processor = scope == Some(Scope::Processor);
+    let section = if processor {
+        "annotation-processors"
+    } else if dev {
+        "dev-dependencies"
+    } else {
+        "dependencies"
+    };
+    let other = if dev {
+        "dependencies"
+    } else {
+        "dev-dependencies"
+    };
+
+    let mut manifest = load_manifest()?;
+    // A processor like Lombok is usually on the compile classpath as well
+    if !processor
+        && manifest
+            .get(other)
+            .and_then(Item::as_table_like)
+            .is_some_and(|t| t.contains_key(&name))
+    {
+        bail!("'{name}' is already in [{other}]; remove it first with `cup remove {name}`");
+    }
+
+    let spec = match scope {
+        Some(scope) if !matches!(scope, Scope::Compile | Scope::Test | Scope::Processor) => {
+            let mut detailed = InlineTable::new();
+            detailed.insert("coordinates", coordinate.to_string().into());
+            detailed.insert("scope", scope.to_string().into());
+            value(detailed)
+        }
+        _ => value(coordinate.to_string()),
+    };
+
+    let table = dependency_table(&mut manifest, section)?;
+    let replaced = table.contains_key(&name);
+    match table.get_mut(&name) {
+        // Keep the comment that may follow the old value
+        Some(Item::Value(existing)) if spec.is_value() => {
+            let suffix = existing.decor().suffix().cloned();
+            *existing = spec.into_value().unwrap();
+            if let Some(suffix) = suffix {
+                existing.decor_mut().set_suffix(suffix);
+            }
+        }
+        _ => {
+            table.insert(&name, spec);
+        }
+    }
+
+    // Resolve before saving, so a typo or a missing artifact leaves Cup.toml as it was
+    resolve_dependencies(&config_from_manifest(&manifest)?, options)?;
+    save_manifest(&manifest)?;
+
+    println!(
+        "{} {name} = \"{coordinate}\" {} [{section}]",
+        if replaced { "Updated" } else { "Added" },
+        if replaced { "in" } else { "to" }
+    );
+    Ok(())
+}
+
+fn dependency_table<'a>(manifest: &'a mut DocumentMut, section: &str) -> Result<&'a mut Table> {
+    manifest
+        .entry(section)
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .with_context(|| format!("[{section}] in Cup.toml is not a table"))
+}
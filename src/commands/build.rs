@@ -1,32 +1,51 @@
-use crate::commands::load_config;
+use crate::commands::resources::copy_main_resources;
+use crate::commands::workspace::{in_workspace_build, run_in_members};
+use crate::commands::{load_config_for, load_workspace_at};
+use crate::deps::{
+    Classpath, ResolveOptions, ResolvedArtifact, build_local_dependencies, on_classpath,
+    resolve_dependencies,
+};
 
 use super::BuildConfig;
+use crate::diagnostics::{self, Summary};
+use crate::failure::Failure;
+use crate::incremental::{Fingerprints, path_stamp, sha256_hex, source_key};
+use crate::report::{self, Event};
+use crate::toolchain::{Toolchain, parse_java_version};
 use anyhow::{Context, Result, bail};
 use dialoguer::console::{Emoji, style};
-use indicatif::ProgressStyle;
-use merkle_hash::MerkleTree;
 use std::{
-    convert::Infallible,
+    collections::BTreeSet,
     fs,
-    io::{Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::Output,
 };
 
 static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍  ", "");
 
-pub fn compile_project() -> Result<()> {
-    let config = load_config()?;
+pub fn compile_project(profile: &str, options: &ResolveOptions) -> Result<()> {
+    if !in_workspace_build()
+        && let Some(workspace) = load_workspace_at(Path::new("."))?
+    {
+        return run_in_members(Path::new("."), &workspace, "build", profile, options);
+    }
 
-    let spinner_style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
-        .unwrap()
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+    let config = load_config_for(profile)?;
+    let toolchain = Toolchain::select(&config)?;
+    report::emit(Event::BuildStarted {
+        command: "build",
+        project: &config.project.name,
+        version: &config.project.version,
+        profile,
+    });
 
-    println!(
+    report::status(format_args!(
         "{} {}Resolving packages...",
         style("[1/4]").bold().dim(),
         LOOKING_GLASS
-    );
+    ));
+    let mut dependencies = resolve_dependencies(&config, options)?;
+    dependencies.extend(build_local_dependencies(&config, profile, options)?);
 
     let build_config = config.build.unwrap_or_default();
     let source_files = discover_source_files(&build_config)?;
@@ -35,23 +54,27 @@ pub fn compile_project() -> Result<()> {
         bail!("No source files found to compile");
     }
 
-    // println!("Found {} source files to compile", source_files.len());
-    println!(
+    report::status(format_args!(
         "{} {}Found {} files...",
         style("[2/4]").bold().dim(),
         LOOKING_GLASS,
         source_files.len()
-    );
+    ));
 
     let output_dir = build_config.output_dir.as_ref().unwrap();
     fs::create_dir_all(output_dir).context("Failed to create output directory")?;
 
-    compile_sources(&source_files, &build_config)?;
+    compile_sources(&source_files, &build_config, &toolchain, &dependencies)?;
+    copy_main_resources(&build_config, &config.project, config.resources.as_ref())?;
     Ok(())
 }
 
 pub fn discover_source_files(build_config: &BuildConfig) -> Result<Vec<PathBuf>> {
-    let source_dir = build_config.source_dir.as_ref().unwrap();
+    discover_sources_in(build_config.source_dir.as_ref().unwrap())
+}
+
+/// Collects the Java and Kotlin sources of a source set such as `src/main` or `src/test`.
+pub fn discover_sources_in(source_dir: &str) -> Result<Vec<PathBuf>> {
     let mut source_files = Vec::new();
 
     let java_dir = Path::new(source_dir).join("java");
@@ -94,106 +117,385 @@ pub fn collect_source_files(dir: &Path, acc: &mut Vec<PathBuf>) -> Result<()> {
     Ok(())
 }
 
-pub fn compile_sources(source_files: &[PathBuf], build_config: &BuildConfig) -> Result<()> {
+pub fn compile_sources(
+    source_files: &[PathBuf],
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+    dependencies: &[ResolvedArtifact],
+) -> Result<()> {
     let output_dir = build_config.output_dir.as_ref().unwrap();
+    let fingerprints_path = fingerprints_path(build_config);
+
+    let output = Path::new(output_dir);
+    let dependency_classpath =
+        build_classpath(&on_classpath(dependencies, Classpath::Compile), toolchain);
 
-    if let Ok(mut file) = std::fs::File::open("Cup.lock") {
-        let tree = MerkleTree::builder("src/")
-            .algorithm(merkle_hash::Algorithm::Blake3)
-            .hash_names(false)
-            .build()?;
-        let mut buf = vec![];
-        let _ = file.read_to_end(&mut buf);
-        // dbg!(&buf);
-        // dbg!(&tree.root.item.hash);
-
-        if tree.root.item.hash == buf {
-            return Ok(());
+    let processing = annotation_processing(dependencies, build_config, "main");
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    // Processors are stamped like the classpath, so a new processor version rebuilds everything
+    let stamped: Vec<&str> = dependency_classpath
+        .iter()
+        .chain(processing.iter().map(|p| &p.path))
+        .map(String::as_str)
+        .collect();
+    let stamped = (!stamped.is_empty()).then(|| stamped.join(separator));
+
+    let mut fingerprints = Fingerprints::load(&fingerprints_path);
+    let build = build_fingerprint(build_config, toolchain, stamped.as_deref())?;
+    if fingerprints.build != build {
+        if !fingerprints.sources.is_empty() {
+            report::status(format_args!(
+                "    {}",
+                style("Compiler, classpath or build settings changed, recompiling everything...")
+                    .dim()
+            ));
         }
+        fingerprints.reset(output)?;
+        fingerprints.build = build;
     }
 
-    let java_files: Vec<&PathBuf> = source_files
+    // Generated sources own the classes compiled from them, so they are cleaned up like any other
+    let mut known_sources = source_files.to_vec();
+    if let Some(processing) = &processing {
+        known_sources.extend(processing.generated_sources()?);
+    }
+    let mut plan = fingerprints.plan(&known_sources, output)?;
+    if plan.is_up_to_date() {
+        report::emit(Event::ArtifactProduced {
+            kind: "classes",
+            path: output,
+            files: vec![],
+            fresh: true,
+        });
+        return Ok(());
+    }
+    if let Some(processing) = &processing {
+        // A processor only sees the sources it runs on, and may generate code from
+        // several of them, so any change runs it on everything again
+        let build = std::mem::take(&mut fingerprints.build);
+        fingerprints.reset(output)?;
+        fingerprints.build = build;
+        processing.clean()?;
+        plan.compile = source_files.to_vec();
+        plan.removed.clear();
+    }
+
+    let languages = match (
+        plan.compile
+            .iter()
+            .any(|f| f.extension().is_some_and(|e| e == "java")),
+        plan.compile
+            .iter()
+            .any(|f| f.extension().is_some_and(|e| e == "kt")),
+    ) {
+        (true, true) => "Java and Kotlin",
+        (false, true) => "Kotlin",
+        _ => "Java",
+    };
+    let count = if plan.compile.len() < source_files.len() {
+        format!(" ({} of {})", plan.compile.len(), source_files.len())
+    } else {
+        String::new()
+    };
+    if plan.compile.is_empty() {
+        report::status(format_args!(
+            "{} {}Removing classes of {} deleted files...",
+            style("[3/4]").bold().dim(),
+            LOOKING_GLASS,
+            plan.removed.len()
+        ));
+    } else {
+        report::status(format_args!(
+            "{} {}Compiling {} Files{}...",
+            style("[3/4]").bold().dim(),
+            LOOKING_GLASS,
+            languages,
+            count
+        ));
+    }
+
+    // Classes of the sources that weren't recompiled are picked up from the output directory
+    let mut classpath_parts = vec![output_dir.to_string()];
+    classpath_parts.extend(dependency_classpath);
+    let classpath = Some(classpath_parts.join(separator));
+
+    // Compile the changed sources, then the users of every class whose ABI changed,
+    // until nothing else is affected
+    let mut compile = plan.compile;
+    let mut invalidated: Vec<String> = plan.removed;
+    let mut done = BTreeSet::new();
+    let mut produced = vec![];
+    loop {
+        invalidated.extend(compile.iter().map(|f| source_key(f)));
+        // Forget what is about to be recompiled first, so a failed compile can't leave it looking fresh
+        let previous = fingerprints.invalidate(invalidated.drain(..), output)?;
+        fingerprints.save(&fingerprints_path)?;
+
+        if !compile.is_empty() {
+            compile_files(
+                &compile,
+                output_dir,
+                &classpath,
+                processing.as_ref(),
+                build_config,
+                toolchain,
+            )?;
+        }
+        for file in &compile {
+            report::emit(Event::FileCompiled { file });
+        }
+        let mut compiled = compile.clone();
+        if let Some(processing) = &processing {
+            compiled.extend(processing.generated_sources()?);
+        }
+        let recorded = fingerprints.record(&compiled, output)?;
+        produced.extend(recorded.iter().map(|name| format!("{name}.class")));
+        done.extend(compile.iter().map(|f| source_key(f)));
+
+        compile = fingerprints.affected(&previous, &recorded, source_files, &done)?;
+        if compile.is_empty() {
+            break;
+        }
+        report::status(format_args!(
+            "    {}",
+            style(format!(
+                "Recompiling {} files that use changed classes...",
+                compile.len()
+            ))
+            .dim()
+        ));
+    }
+    fingerprints.prune();
+    fingerprints.save(&fingerprints_path)?;
+    report::emit(Event::ArtifactProduced {
+        kind: "classes",
+        path: output,
+        files: produced,
+        fresh: false,
+    });
+
+    let summary = Summary::total();
+    report::status(format_args!(
+        "{} {}Compilation Successful!!!{}",
+        style("[4/4]").bold().dim(),
+        LOOKING_GLASS,
+        if summary.is_empty() {
+            String::new()
+        } else {
+            format!(" ({summary})")
+        }
+    ));
+
+    Ok(())
+}
+
+/// Compiles a set of Java and/or Kotlin sources into `output_dir`.
+pub fn compile_files(
+    source_files: &[PathBuf],
+    output_dir: &str,
+    classpath: &Option<String>,
+    processing: Option<&AnnotationProcessing>,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+) -> Result<()> {
+    let mut java_files: Vec<&PathBuf> = source_files
         .iter()
         .filter(|f| f.extension().is_some_and(|ext| ext == "java"))
         .collect();
 
-    let kotlin_files: Vec<&PathBuf> = source_files
+    let mut kotlin_files: Vec<&PathBuf> = source_files
         .iter()
         .filter(|f| f.extension().is_some_and(|ext| ext == "kt"))
         .collect();
 
-    let classpath = build_classpath();
+    // With kapt the processors see both languages up front, and the sources they
+    // generate are compiled with the rest, without running the processors again
+    let generated;
+    let mut processors = match processing {
+        Some(processing) => Processors::Javac(processing),
+        None => Processors::None,
+    };
+    if let Some(kapt) = processing.filter(|p| p.kapt)
+        && !kotlin_files.is_empty()
+    {
+        run_kapt(
+            &kotlin_files,
+            &java_files,
+            output_dir,
+            classpath,
+            kapt,
+            build_config,
+            toolchain,
+        )?;
+        generated = kapt.generated_sources()?;
+        java_files.extend(
+            generated
+                .iter()
+                .filter(|f| f.extension().is_some_and(|ext| ext == "java")),
+        );
+        kotlin_files.extend(
+            generated
+                .iter()
+                .filter(|f| f.extension().is_some_and(|ext| ext == "kt")),
+        );
+        processors = Processors::Done;
+    }
 
     // If we have both Java and Kotlin files, we need to compile them in phases
     if !java_files.is_empty() && !kotlin_files.is_empty() {
-        // println!("Compiling mixed Java/Kotlin project in two phases...");
-        println!(
-            "{} {}Compiling Java and Kotlin Files...",
-            style("[3/4]").bold().dim(),
-            LOOKING_GLASS
-        );
         compile_mixed_project(
             &java_files,
             &kotlin_files,
             output_dir,
-            &classpath,
+            classpath,
+            processors,
             build_config,
+            toolchain,
         )?;
     } else if !kotlin_files.is_empty() {
-        // println!("Compiling Kotlin files...");
-        println!(
-            "{} {}Compiling Kotlin Files...",
-            style("[3/4]").bold().dim(),
-            LOOKING_GLASS
-        );
-        compile_kotlin_files(&kotlin_files, output_dir, &classpath, build_config)?;
+        compile_kotlin_files(
+            &kotlin_files,
+            output_dir,
+            classpath,
+            build_config,
+            toolchain,
+        )?;
     } else if !java_files.is_empty() {
-        println!(
-            "{} {}Compiling Java Files...",
-            style("[3/4]").bold().dim(),
-            LOOKING_GLASS
-        );
-        compile_java_files(&java_files, output_dir, &classpath)?;
+        compile_java_files(
+            &java_files,
+            output_dir,
+            classpath,
+            processors,
+            build_config,
+            toolchain,
+        )?;
     }
-    let tree = MerkleTree::builder("src/")
-        .algorithm(merkle_hash::Algorithm::Blake3)
-        .hash_names(false)
-        .build()?;
-    if let Ok(mut file) = std::fs::File::create("Cup.lock") {
-        let _ = file
-            .write(&tree.root.item.hash)
-            .expect("error writing Cup.lock");
+
+    Ok(())
+}
+
+/// Whether javac runs the annotation processors.
+#[derive(Clone, Copy)]
+enum Processors<'a> {
+    None,
+    Javac(&'a AnnotationProcessing),
+    /// kapt has already run them over the Java and Kotlin sources
+    Done,
+}
+
+/// `[annotation-processors]` for one source set: javac's `-processorpath` and the
+/// directory processors write their sources to.
+pub struct AnnotationProcessing {
+    pub path: String,
+    pub generated_dir: PathBuf,
+    /// Run by kapt rather than javac, see `[kotlin] kapt`
+    pub kapt: bool,
+    /// Java stubs kapt generates for the Kotlin sources
+    pub stubs_dir: PathBuf,
+}
+
+impl AnnotationProcessing {
+    pub fn generated_sources(&self) -> Result<Vec<PathBuf>> {
+        let mut sources = vec![];
+        if self.generated_dir.exists() {
+            collect_source_files(&self.generated_dir, &mut sources)?;
+        }
+        Ok(sources)
     }
 
-    println!(
-        "{} {}Compilation Successful!!!",
-        style("[4/4]").bold().dim(),
-        LOOKING_GLASS
-    );
+    /// Removes what the processors generated last time, before they run again.
+    pub fn clean(&self) -> Result<()> {
+        for dir in [&self.generated_dir, &self.stubs_dir] {
+            if dir.exists() {
+                fs::remove_dir_all(dir)
+                    .with_context(|| format!("Failed to delete {}", dir.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
 
-    Ok(())
+/// Annotation processing for `source_set` (`main` or `test`), if the project has processors.
+pub fn annotation_processing(
+    dependencies: &[ResolvedArtifact],
+    build_config: &BuildConfig,
+    source_set: &str,
+) -> Option<AnnotationProcessing> {
+    let processors = on_classpath(dependencies, Classpath::Processor);
+    if processors.is_empty() {
+        return None;
+    }
+    let path: Vec<String> = processors
+        .iter()
+        .map(|d| d.path.to_string_lossy().to_string())
+        .collect();
+    let build_dir = Path::new(build_config.output_dir.as_ref().unwrap())
+        .parent()
+        .unwrap_or(Path::new("build"));
+    Some(AnnotationProcessing {
+        path: path.join(if cfg!(windows) { ";" } else { ":" }),
+        generated_dir: generated_sources_dir(build_config, source_set),
+        kapt: build_config
+            .kotlin
+            .as_ref()
+            .is_some_and(|k| k.kapt == Some(true)),
+        stubs_dir: build_dir.join("tmp/kapt3/stubs").join(source_set),
+    })
+}
+
+/// Where annotation processors put the sources they generate for `source_set`.
+pub fn generated_sources_dir(build_config: &BuildConfig, source_set: &str) -> PathBuf {
+    let output_dir = Path::new(build_config.output_dir.as_ref().unwrap());
+    output_dir
+        .parent()
+        .unwrap_or(Path::new("build"))
+        .join("generated/sources/annotationProcessor")
+        .join(source_set)
+}
+
+/// Directory next to the output directory where Cup keeps its build caches.
+pub fn cache_dir(build_config: &BuildConfig) -> PathBuf {
+    let output_dir = Path::new(build_config.output_dir.as_ref().unwrap());
+    output_dir
+        .parent()
+        .unwrap_or(Path::new("build"))
+        .join(".cup")
+}
+
+/// Where the compiled tests go, next to the main output directory.
+pub fn test_output_dir(build_config: &BuildConfig) -> PathBuf {
+    let output_dir = Path::new(build_config.output_dir.as_ref().unwrap());
+    output_dir
+        .parent()
+        .unwrap_or(Path::new("build"))
+        .join("test-classes")
+}
+
+/// Per-source fingerprints of the last compilation, see [`Fingerprints`].
+pub fn fingerprints_path(build_config: &BuildConfig) -> PathBuf {
+    cache_dir(build_config).join("fingerprints.toml")
 }
 
+/// Compiles the Kotlin sources first, with the Java sources for kotlinc to resolve
+/// references, then the Java sources against the Kotlin classes.
 fn compile_mixed_project(
     java_files: &[&PathBuf],
     kotlin_files: &[&PathBuf],
     output_dir: &str,
     classpath: &Option<String>,
+    processors: Processors,
     build_config: &BuildConfig,
+    toolchain: &Toolchain,
 ) -> Result<()> {
-    // println!("Compiling mixed Java/Kotlin project in two phases...");
-
-    // println!("Phase 1: Compiling Kotlin files with Java source references...");
-    let _ = compile_kotlin_with_java_sources(
+    compile_kotlin_with_java_sources(
         kotlin_files,
         java_files,
         output_dir,
         classpath,
         build_config,
-    )
-    .inspect_err(|e| eprintln!("{:?}", e));
+        toolchain,
+    )?;
 
-    // println!("Phase 2: Compiling Java files with Kotlin classes in classpath...");
     let mut extended_classpath = vec![];
 
     // Add original classpath if it exists
@@ -210,12 +512,14 @@ fn compile_mixed_project(
         Some(output_dir.to_string())
     };
 
-    let _ = compile_java_files(java_files, output_dir, &combined_classpath)
-        .inspect_err(|e| eprintln!("{:?}", e));
-
-    // TODO: make a Cup.lock to hash the files and prevent successive needless compilations
-
-    Ok(())
+    compile_java_files(
+        java_files,
+        output_dir,
+        &combined_classpath,
+        processors,
+        build_config,
+        toolchain,
+    )
 }
 
 fn compile_kotlin_with_java_sources(
@@ -223,10 +527,12 @@ fn compile_kotlin_with_java_sources(
     java_files: &[&PathBuf],
     output_dir: &str,
     classpath: &Option<String>,
-    _build_config: &BuildConfig,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
 ) -> Result<()> {
-    let mut cmd = Command::new("kotlinc");
+    let mut cmd = toolchain.kotlinc();
     cmd.arg("-d").arg(output_dir);
+    cmd.args(kotlinc_args(build_config, toolchain)?);
 
     if let Some(cp) = classpath {
         cmd.arg("-cp").arg(cp);
@@ -244,25 +550,79 @@ fn compile_kotlin_with_java_sources(
 
     let output = cmd
         .output()
-        .context("Failed to execute kotlinc. Make sure Kotlin is installed and in PATH.")
-        .inspect_err(|e| eprintln!("{e}"));
+        .context("Failed to execute kotlinc. Make sure Kotlin is installed and in PATH.")?;
+
+    check_compiler_output(&output, "Kotlin compilation with Java sources")
+}
 
-    if !output.unwrap().status.success() {
-        // let stderr = String::from_utf8_lossy(&output.;
-        bail!("Kotlin compilation with Java sources failed:\n{}", "a");
+/// Runs kapt, which generates Java stubs for the Kotlin sources and runs the annotation
+/// processors over them and the Java sources, without compiling anything.
+fn run_kapt(
+    kotlin_files: &[&PathBuf],
+    java_files: &[&PathBuf],
+    output_dir: &str,
+    classpath: &Option<String>,
+    processing: &AnnotationProcessing,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+) -> Result<()> {
+    let lib = toolchain
+        .kotlin_lib()
+        .context("kapt needs a Kotlin compiler, but none was found")?;
+    let jar = lib.join("kotlin-annotation-processing.jar");
+    if !jar.is_file() {
+        bail!("kapt is not available: {} does not exist", jar.display());
+    }
+    for dir in [&processing.generated_dir, &processing.stubs_dir] {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
     }
 
-    Ok(())
+    let mut cmd = toolchain.kotlinc();
+    cmd.args(kotlinc_args(build_config, toolchain)?);
+    cmd.arg(format!("-Xplugin={}", jar.display()));
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    let mut options = vec![
+        format!("sources={}", processing.generated_dir.display()),
+        format!("classes={output_dir}"),
+        format!("stubs={}", processing.stubs_dir.display()),
+        "aptMode=stubsAndApt".to_string(),
+    ];
+    options.extend(
+        processing
+            .path
+            .split(separator)
+            .map(|entry| format!("apclasspath={entry}")),
+    );
+    for option in options {
+        cmd.arg("-P")
+            .arg(format!("plugin:org.jetbrains.kotlin.kapt3:{option}"));
+    }
+
+    if let Some(cp) = classpath {
+        cmd.arg("-cp").arg(cp);
+    }
+
+    for file in kotlin_files.iter().chain(java_files) {
+        cmd.arg(file);
+    }
+
+    let output = cmd
+        .output()
+        .context("Failed to execute kotlinc. Make sure Kotlin is installed and in PATH.")?;
+
+    check_compiler_output(&output, "Annotation processing with kapt")
 }
 
 fn compile_kotlin_files(
     kotlin_files: &[&PathBuf],
     output_dir: &str,
     classpath: &Option<String>,
-    _build_config: &BuildConfig,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
 ) -> Result<()> {
-    let mut cmd = Command::new("kotlinc");
+    let mut cmd = toolchain.kotlinc();
     cmd.arg("-d").arg(output_dir);
+    cmd.args(kotlinc_args(build_config, toolchain)?);
 
     if let Some(cp) = classpath {
         cmd.arg("-cp").arg(cp);
@@ -276,26 +636,40 @@ fn compile_kotlin_files(
         .output()
         .context("Failed to execute kotlinc. Make sure Kotlin is installed and in PATH.")?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("Kotlin compilation failed:\n{}", stderr);
-    }
-
-    Ok(())
+    check_compiler_output(&output, "Kotlin compilation")
 }
 
 fn compile_java_files(
     java_files: &[&PathBuf],
     output_dir: &str,
     classpath: &Option<String>,
+    processors: Processors,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
 ) -> Result<()> {
-    let mut cmd = Command::new("javac");
+    let mut cmd = toolchain.javac();
     cmd.arg("-d").arg(output_dir);
+    cmd.args(javac_args(build_config, toolchain)?);
 
     if let Some(cp) = classpath {
         cmd.arg("-cp").arg(cp);
     }
 
+    match processors {
+        Processors::None => {}
+        Processors::Javac(processing) => {
+            fs::create_dir_all(&processing.generated_dir).with_context(|| {
+                format!("Failed to create {}", processing.generated_dir.display())
+            })?;
+            cmd.arg("-processorpath").arg(&processing.path);
+            cmd.arg("-s").arg(&processing.generated_dir);
+        }
+        // Otherwise javac looks for processors on the classpath and runs them a second time
+        Processors::Done => {
+            cmd.arg("-proc:none");
+        }
+    }
+
     for file in java_files {
         cmd.arg(file);
     }
@@ -304,17 +678,281 @@ fn compile_java_files(
         .output()
         .context("Failed to execute javac. Make sure Java is installed and in PATH.")?;
 
+    check_compiler_output(&output, "Java compilation")
+}
+
+/// Reports what the compiler printed, failing with a count of its errors if it did not succeed.
+fn check_compiler_output(output: &Output, what: &str) -> Result<()> {
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let diagnostics = diagnostics::parse(&printed);
+    diagnostics::report(&diagnostics);
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("Java compilation failed:\n{}", stderr);
+        let summary = Summary::of(&diagnostics);
+        let message = if summary.errors == 0 {
+            format!("{what} failed")
+        } else {
+            format!("{what} failed with {summary}")
+        };
+        return Err(Failure::Compile(message).into());
     }
-
     Ok(())
 }
 
-pub fn build_classpath() -> Option<String> {
+/// Hash of everything besides the sources that affects the compiled classes: the `[build]`
+/// and `[kotlin]` settings with the profile applied, the toolchain, compiler flags and every
+/// classpath entry. When it changes, the next build starts from scratch.
+fn build_fingerprint(
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+    classpath: Option<&str>,
+) -> Result<String> {
+    let mut inputs = vec![
+        // Not all of Cup.toml: `[run]`, the version or a comment don't change the classes
+        format!("build {}", serde_json::to_string(build_config)?),
+        format!("kotlin {}", serde_json::to_string(&build_config.kotlin)?),
+        format!("toolchain {}", toolchain.fingerprint()),
+        format!(
+            "flags {}",
+            compiler_flags(build_config, toolchain)?.join(" ")
+        ),
+    ];
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    for entry in classpath.into_iter().flat_map(|cp| cp.split(separator)) {
+        inputs.push(format!(
+            "classpath {entry} {}",
+            path_stamp(Path::new(entry))
+        ));
+    }
+    Ok(sha256_hex(inputs.join("\n").as_bytes()))
+}
+
+/// Flags passed to the compilers besides sources, classpath and output directory.
+fn compiler_flags(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let mut flags = javac_args(build_config, toolchain)?;
+    flags.extend(kotlinc_args(build_config, toolchain)?);
+    Ok(flags)
+}
+
+/// The target release plus `[build.javac]`.
+fn javac_args(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let mut args = java_target_args(build_config, toolchain)?;
+    let Some(javac) = &build_config.javac else {
+        return Ok(args);
+    };
+    if let Some(encoding) = &javac.encoding {
+        args.extend(["-encoding".to_string(), encoding.clone()]);
+    }
+    match javac.debug {
+        Some(true) => args.push("-g".to_string()),
+        Some(false) => args.push("-g:none".to_string()),
+        None => {}
+    }
+    if javac.parameters == Some(true) {
+        args.push("-parameters".to_string());
+    }
+    args.extend(javac.args.iter().flatten().cloned());
+    Ok(args)
+}
+
+/// The JDK and JVM target plus `[build.kotlinc]`.
+fn kotlinc_args(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let mut args = kotlin_target_args(build_config, toolchain)?;
+    // Compile against the selected JDK's classes, not the ones kotlinc itself runs on
+    if let Some(jdk) = &toolchain.jdk {
+        args.extend(["-jdk-home".to_string(), jdk.home.display().to_string()]);
+    }
+    args.extend(kotlin_plugin_args(build_config, toolchain)?);
+    let Some(kotlinc) = &build_config.kotlinc else {
+        return Ok(args);
+    };
+    if let Some(encoding) = &kotlinc.encoding
+        && !matches!(encoding.to_lowercase().as_str(), "utf-8" | "utf8")
+    {
+        return Err(Failure::Config(format!(
+            "kotlinc always reads sources as UTF-8, encoding = \"{encoding}\" in [build.kotlinc] is not supported"
+        ))
+        .into());
+    }
+    if kotlinc.debug == Some(false) {
+        return Err(Failure::Config(
+            "kotlinc always writes debug information, remove debug = false from [build.kotlinc]"
+                .to_string(),
+        )
+        .into());
+    }
+    if kotlinc.parameters == Some(true) {
+        args.push("-java-parameters".to_string());
+    }
+    args.extend(kotlinc.args.iter().flatten().cloned());
+    Ok(args)
+}
+
+/// `-Xplugin` and `-P` arguments for the compiler plugins in `[kotlin]`.
+fn kotlin_plugin_args(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let Some(kotlin) = &build_config.kotlin else {
+        return Ok(vec![]);
+    };
+    let mut args = vec![];
+    for plugin in kotlin.plugins.iter().flatten() {
+        let jar = if plugin.ends_with(".jar") {
+            PathBuf::from(plugin)
+        } else {
+            let Some((jar, _, _)) = KOTLIN_PLUGINS.iter().find(|(_, name, _)| name == plugin)
+            else {
+                let known: Vec<&str> = KOTLIN_PLUGINS.iter().map(|(_, name, _)| *name).collect();
+                return Err(Failure::Config(format!(
+                    "Unknown Kotlin compiler plugin '{plugin}' in [kotlin] plugins. Use one of {} or the path to a plugin jar.",
+                    known.join(", ")
+                ))
+                .into());
+            };
+            toolchain
+                .kotlin_lib()
+                .with_context(|| {
+                    format!("The {plugin} plugin needs a Kotlin compiler, but none was found")
+                })?
+                .join(jar)
+        };
+        if !jar.is_file() {
+            bail!(
+                "Kotlin compiler plugin '{plugin}' not found at {}",
+                jar.display()
+            );
+        }
+        args.push(format!("-Xplugin={}", jar.display()));
+
+        // `spring` and `jpa` are all-open and no-arg with their presets
+        match plugin.as_str() {
+            "spring" => args.extend([
+                "-P".to_string(),
+                "plugin:org.jetbrains.kotlin.allopen:preset=spring".to_string(),
+            ]),
+            "jpa" => args.extend([
+                "-P".to_string(),
+                "plugin:org.jetbrains.kotlin.noarg:preset=jpa".to_string(),
+            ]),
+            _ => {}
+        }
+    }
+    for (plugin, options) in kotlin.plugin_options.iter().flatten() {
+        let id = KOTLIN_PLUGINS
+            .iter()
+            .find(|(_, name, _)| name == plugin)
+            .map_or(plugin.as_str(), |(_, _, id)| id);
+        for option in options {
+            args.extend(["-P".to_string(), format!("plugin:{id}:{option}")]);
+        }
+    }
+    Ok(args)
+}
+
+/// Plugins shipped in kotlinc's `lib` directory: jar, name in `[kotlin] plugins`, plugin id.
+const KOTLIN_PLUGINS: &[(&str, &str, &str)] = &[
+    (
+        "allopen-compiler-plugin.jar",
+        "all-open",
+        "org.jetbrains.kotlin.allopen",
+    ),
+    (
+        "allopen-compiler-plugin.jar",
+        "spring",
+        "org.jetbrains.kotlin.allopen",
+    ),
+    (
+        "noarg-compiler-plugin.jar",
+        "no-arg",
+        "org.jetbrains.kotlin.noarg",
+    ),
+    (
+        "noarg-compiler-plugin.jar",
+        "jpa",
+        "org.jetbrains.kotlin.noarg",
+    ),
+    (
+        "kotlinx-serialization-compiler-plugin.jar",
+        "serialization",
+        "org.jetbrains.kotlinx.serialization",
+    ),
+    (
+        "sam-with-receiver-compiler-plugin.jar",
+        "sam-with-receiver",
+        "org.jetbrains.kotlin.samWithReceiver",
+    ),
+    (
+        "lombok-compiler-plugin.jar",
+        "lombok",
+        "org.jetbrains.kotlin.lombok",
+    ),
+    (
+        "power-assert-compiler-plugin.jar",
+        "power-assert",
+        "org.jetbrains.kotlin.powerassert",
+    ),
+];
+
+/// `--release` (or `-source`/`-target` on JDK 8) for `java_version`, after
+/// checking that the JDK can produce class files for that release.
+fn java_target_args(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let Some(release) = check_java_version(build_config, toolchain)? else {
+        return Ok(vec![]);
+    };
+    if toolchain.java_release()? >= 9 {
+        Ok(vec!["--release".to_string(), release.to_string()])
+    } else {
+        Ok(vec![
+            "-source".to_string(),
+            format!("1.{release}"),
+            "-target".to_string(),
+            format!("1.{release}"),
+        ])
+    }
+}
+
+fn kotlin_target_args(build_config: &BuildConfig, toolchain: &Toolchain) -> Result<Vec<String>> {
+    let Some(release) = check_java_version(build_config, toolchain)? else {
+        return Ok(vec![]);
+    };
+    let target = if release <= 8 {
+        format!("1.{release}")
+    } else {
+        release.to_string()
+    };
+    Ok(vec!["-jvm-target".to_string(), target])
+}
+
+/// The release `java_version` asks for, failing if the selected JDK is older.
+pub fn check_java_version(
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+) -> Result<Option<u32>> {
+    let Some(java_version) = &build_config.java_version else {
+        return Ok(None);
+    };
+    let release = parse_java_version(java_version)
+        .with_context(|| format!("invalid java_version '{java_version}' in Cup.toml"))?;
+    let jdk = toolchain.java_release()?;
+    if jdk < release {
+        let found = match &toolchain.jdk {
+            Some(installation) => format!("the selected JDK is {installation}"),
+            None => format!("the javac in PATH is JDK {jdk}"),
+        };
+        bail!(
+            "java_version = \"{java_version}\" in Cup.toml needs JDK {release} or newer, but {found}"
+        );
+    }
+    Ok(Some(release))
+}
+
+pub fn build_classpath(dependencies: &[ResolvedArtifact], toolchain: &Toolchain) -> Option<String> {
     let lib_dir = Path::new("lib");
-    let mut jars = vec![];
+    let mut jars: Vec<String> = dependencies
+        .iter()
+        .map(|d| d.path.to_string_lossy().to_string())
+        .collect();
 
     if lib_dir.exists()
         && let Ok(entries) = fs::read_dir(lib_dir)
@@ -327,19 +965,8 @@ pub fn build_classpath() -> Option<String> {
     }
 
     // Always include Kotlin runtime for Kotlin projects
-    // Try to find Kotlin runtime in common locations
-    let kotlin_runtime_paths = [
-        "/usr/share/kotlin/lib/kotlin-stdlib.jar",
-        "/opt/kotlin/lib/kotlin-stdlib.jar",
-        "~/.kotlinc/lib/kotlin-stdlib.jar",
-        "/usr/local/share/kotlin/lib/kotlin-stdlib.jar",
-    ];
-
-    for path in &kotlin_runtime_paths {
-        if Path::new(path).exists() {
-            jars.push(path.to_string());
-            break;
-        }
+    if let Some(stdlib) = toolchain.kotlin_stdlib() {
+        jars.push(stdlib.to_string_lossy().to_string());
     }
 
     if !jars.is_empty() {
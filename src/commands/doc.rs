@@ -1,19 +1,24 @@
-use std::process::Command;
-
 use anyhow::{Context, Result, bail};
-use clap::builder::OsStr;
 
+use crate::commands::build::{collect_source_files, generated_sources_dir};
 use crate::commands::{discover_java_files, load_config};
+use crate::toolchain::Toolchain;
 
 pub fn create_documentation() -> Result<()> {
     let config = load_config()?;
+    let toolchain = Toolchain::select(&config)?;
     println!(
         "Building project: {} v{}",
         config.project.name, config.project.version
     );
 
     let build_config = config.build.unwrap_or_default();
-    let java_files = discover_java_files(&build_config)?;
+    let mut java_files = discover_java_files(&build_config)?;
+    // Sources written by annotation processors during the last build
+    let generated = generated_sources_dir(&build_config, "main");
+    if generated.exists() {
+        collect_source_files(&generated, &mut java_files)?;
+    }
 
     if java_files.is_empty() {
         bail!("No Java files to make documentation");
@@ -22,7 +27,7 @@ pub fn create_documentation() -> Result<()> {
     println!("Found {} Java files to document", java_files.len());
 
     let doc_dir = build_config.doc_dir.clone().unwrap();
-    let mut cmd = Command::new("javadoc");
+    let mut cmd = toolchain.javadoc();
     for i in java_files {
         if i.extension().is_some_and(|ext| ext == "java") {
             cmd.arg(i);
@@ -1,24 +1,43 @@
 use std::{
+    collections::BTreeMap,
     fs,
-    io::Read,
     path::{Path, PathBuf},
 };
 
-use merkle_hash::MerkleTree;
 use serde::{Deserialize, Serialize};
-use toml::Table;
+use toml_edit::DocumentMut;
+
+use crate::deps::{LOCKFILE, Scope};
+use crate::failure::Failure;
 
 use anyhow::{Context, Result, bail};
+pub mod add;
 pub mod build;
 pub mod doc;
 pub mod new;
+pub mod remove;
+pub mod resources;
 pub mod run;
+pub mod test;
+pub mod toolchain;
+pub mod tree;
+pub mod workspace;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CupConfig {
     pub project: ProjectConfig,
     pub build: Option<BuildConfig>,
-    pub dependencies: Option<Table>,
+    pub dependencies: Option<BTreeMap<String, DependencySpec>>,
+    #[serde(rename = "dev-dependencies")]
+    pub dev_dependencies: Option<BTreeMap<String, DependencySpec>>,
+    #[serde(rename = "annotation-processors")]
+    pub annotation_processors: Option<BTreeMap<String, DependencySpec>>,
+    pub repositories: Option<Vec<RepositoryConfig>>,
+    pub toolchain: Option<ToolchainConfig>,
+    pub resources: Option<ResourcesConfig>,
+    pub run: Option<RunConfig>,
+    pub profile: Option<BTreeMap<String, ProfileConfig>>,
+    pub kotlin: Option<KotlinConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -36,6 +55,150 @@ pub struct BuildConfig {
     pub test_dir: Option<String>,
     pub java_version: Option<String>,
     pub doc_dir: Option<String>,
+    pub javac: Option<CompilerConfig>,
+    pub kotlinc: Option<CompilerConfig>,
+    /// Copy of `[kotlin]`, which applies to every kotlinc run
+    #[serde(skip)]
+    pub kotlin: Option<KotlinConfig>,
+}
+
+/// `[build.javac]` / `[build.kotlinc]`: extra compiler options.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CompilerConfig {
+    /// Passed to the compiler as they are, e.g. `["-Xlint:all", "-Werror"]`
+    pub args: Option<Vec<String>>,
+    /// Source file encoding
+    pub encoding: Option<String>,
+    /// `false` leaves out debug information (`-g:none`)
+    pub debug: Option<bool>,
+    /// Keep method parameter names for reflection
+    pub parameters: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum DependencySpec {
+    /// `"group:artifact:version"`
+    Simple(String),
+    Detailed(DetailedDependency),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DetailedDependency {
+    pub coordinates: Option<String>,
+    pub scope: Option<Scope>,
+    /// Another Cup project on disk
+    pub path: Option<String>,
+    /// Git repository holding another Cup project
+    pub git: Option<String>,
+    pub rev: Option<String>,
+}
+
+/// `[workspace]` in the root `Cup.toml` of a multi-module project.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkspaceConfig {
+    /// Member project directories, relative to the workspace root
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkspaceManifest {
+    workspace: Option<WorkspaceConfig>,
+    project: Option<toml::Table>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RepositoryConfig {
+    pub name: Option<String>,
+    /// HTTP(S) mirror or local directory in Maven layout
+    pub url: String,
+}
+
+/// `[toolchain]`: which installed JDK and Kotlin compiler to build with.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolchainConfig {
+    /// JDK version, e.g. `"21"` or `"21.0.2"`; defaults to the one `java_version` needs
+    pub java: Option<String>,
+    /// Part of the JDK's `IMPLEMENTOR`, e.g. `"Adoptium"`
+    pub vendor: Option<String>,
+    /// Kotlin compiler version, e.g. `"2.0"`
+    pub kotlin: Option<String>,
+}
+
+/// `[resources]`: how files from `src/main/resources` are copied next to the classes.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResourcesConfig {
+    /// Replace `${project.version}`, `${project.name}` and `${<property>}` in text resources
+    pub filter: Option<bool>,
+    /// Values for `${<property>}`
+    pub properties: Option<BTreeMap<String, String>>,
+}
+
+/// `[run]`: how `cup run` starts the JVM.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunConfig {
+    /// Options for the JVM, e.g. `"-Xmx2g"`
+    pub jvm_args: Option<Vec<String>>,
+    /// Passed as `-D<name>=<value>`
+    pub system_properties: Option<BTreeMap<String, String>>,
+    /// Environment variables for the program, on top of Cup's own environment
+    pub env: Option<BTreeMap<String, String>>,
+    /// Directory the program runs in, relative to the project
+    pub working_dir: Option<String>,
+}
+
+impl RunConfig {
+    /// Adds the command-line options to the ones from Cup.toml.
+    pub fn merge(self, overrides: Self) -> Self {
+        let mut jvm_args = self.jvm_args.unwrap_or_default();
+        jvm_args.extend(overrides.jvm_args.unwrap_or_default());
+        let mut system_properties = self.system_properties.unwrap_or_default();
+        system_properties.extend(overrides.system_properties.unwrap_or_default());
+        let mut env = self.env.unwrap_or_default();
+        env.extend(overrides.env.unwrap_or_default());
+        Self {
+            jvm_args: Some(jvm_args),
+            system_properties: Some(system_properties),
+            env: Some(env),
+            working_dir: overrides.working_dir.or(self.working_dir),
+        }
+    }
+}
+
+/// `[kotlin]`: compiler plugins and kapt.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KotlinConfig {
+    /// Plugins bundled with kotlinc, e.g. `"all-open"` or `"serialization"`, or paths to plugin jars
+    pub plugins: Option<Vec<String>>,
+    /// `option=value` pairs for each plugin, by plugin name or id
+    #[serde(rename = "plugin-options")]
+    pub plugin_options: Option<BTreeMap<String, Vec<String>>>,
+    /// Run `[annotation-processors]` with kapt, so they see the Kotlin sources too
+    pub kapt: Option<bool>,
+}
+
+/// Profile used when `--profile` is not given. It builds into `[build] output_dir`.
+pub const DEFAULT_PROFILE: &str = "dev";
+
+/// `[profile.<name>]`: overrides for `--profile <name>`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    /// Defaults to `<name>/classes` next to `[build] output_dir`
+    pub output_dir: Option<String>,
+    pub javac: Option<CompilerConfig>,
+    pub kotlinc: Option<CompilerConfig>,
+    pub resources: Option<ResourcesConfig>,
+}
+
+impl CompilerConfig {
+    fn merge(self, overrides: Self) -> Self {
+        Self {
+            args: overrides.args.or(self.args),
+            encoding: overrides.encoding.or(self.encoding),
+            debug: overrides.debug.or(self.debug),
+            parameters: overrides.parameters.or(self.parameters),
+        }
+    }
 }
 
 impl Default for BuildConfig {
@@ -46,41 +209,208 @@ impl Default for BuildConfig {
             test_dir: Some("src/test".to_string()),
             java_version: Some("11".to_string()),
             doc_dir: Some("doc".to_string()),
+            javac: None,
+            kotlinc: None,
+            kotlin: None,
         }
     }
 }
 
-pub fn check_dir_for_changes() -> Result<bool> {
-    if let Ok(mut file) = std::fs::File::open("Cup.lock") {
-        let tree = MerkleTree::builder("src/")
-            .algorithm(merkle_hash::Algorithm::Blake3)
-            .hash_names(false)
-            .build()?;
-        let mut buf = vec![];
-        let _ = file.read_to_end(&mut buf);
-        dbg!(&buf);
-        dbg!(&tree.root.item.hash);
+pub fn load_config() -> Result<CupConfig> {
+    load_config_at(Path::new("."))
+}
+
+/// Reads `Cup.toml` with `[profile.<profile>]` applied on top of `[build]` and `[resources]`.
+pub fn load_config_for(profile: &str) -> Result<CupConfig> {
+    let mut config = load_config()?;
+    config.apply_profile(profile).map_err(Failure::config)?;
+    Ok(config)
+}
+
+impl CupConfig {
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid profile name '{name}'");
+        }
+        let profile = match self.profile.as_ref().and_then(|p| p.get(name)) {
+            Some(profile) => profile.clone(),
+            None if name == DEFAULT_PROFILE || name == "release" => ProfileConfig::default(),
+            None => {
+                bail!("Profile '{name}' is not defined. Add a [profile.{name}] table to Cup.toml.")
+            }
+        };
+
+        let build = self.build.get_or_insert_with(BuildConfig::default);
+        if let Some(output_dir) = profile.output_dir {
+            build.output_dir = Some(output_dir);
+        } else if name != DEFAULT_PROFILE {
+            // build/classes -> build/release/classes, which also keeps its caches apart
+            let output_dir = PathBuf::from(build.output_dir.as_ref().unwrap());
+            let parent = output_dir.parent().unwrap_or(Path::new(""));
+            let file_name = output_dir.file_name().unwrap_or("classes".as_ref());
+            build.output_dir = Some(
+                parent
+                    .join(name)
+                    .join(file_name)
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        if let Some(javac) = profile.javac {
+            build.javac = Some(build.javac.take().unwrap_or_default().merge(javac));
+        }
+        if let Some(kotlinc) = profile.kotlinc {
+            build.kotlinc = Some(build.kotlinc.take().unwrap_or_default().merge(kotlinc));
+        }
 
-        if tree.root.item.hash == buf {
-            return Ok(true);
+        if let Some(overrides) = profile.resources {
+            let resources = self.resources.get_or_insert_with(ResourcesConfig::default);
+            if overrides.filter.is_some() {
+                resources.filter = overrides.filter;
+            }
+            if let Some(properties) = overrides.properties {
+                resources
+                    .properties
+                    .get_or_insert_with(BTreeMap::new)
+                    .extend(properties);
+            }
         }
+        Ok(())
     }
-    Ok(false)
 }
 
-pub fn load_config() -> Result<CupConfig> {
-    let config_content = fs::read_to_string("Cup.toml")
-        .context("Failed to read Cup.toml. Make sure it exists in the project root.")?;
-    let config: CupConfig = toml::from_str(&config_content).context("failed to import config")?;
+/// Reads the `Cup.toml` of the project in `dir`. Workspace members that keep the
+/// default output directory get one under the shared `build/` of the workspace.
+pub fn load_config_at(dir: &Path) -> Result<CupConfig> {
+    read_config_at(dir).map_err(Failure::config)
+}
+
+fn read_config_at(dir: &Path) -> Result<CupConfig> {
+    let path = dir.join("Cup.toml");
+    let config_content = fs::read_to_string(&path).with_context(|| {
+        format!(
+            "Failed to read {}. Make sure it exists in the project root.",
+            path.display()
+        )
+    })?;
+    parse_config(&config_content, dir)
+}
+
+/// The config of an edited `Cup.toml` that has not been saved yet.
+pub fn config_from_manifest(manifest: &DocumentMut) -> Result<CupConfig> {
+    parse_config(&manifest.to_string(), Path::new(".")).map_err(Failure::config)
+}
+
+fn parse_config(config_content: &str, dir: &Path) -> Result<CupConfig> {
+    let path = dir.join("Cup.toml");
+    if toml::from_str::<WorkspaceManifest>(config_content)
+        .is_ok_and(|m| m.workspace.is_some() && m.project.is_none())
+    {
+        bail!(
+            "{} is a workspace root without a [project]; run this command in one of its members",
+            path.display()
+        );
+    }
+    let mut config: CupConfig =
+        toml::from_str(config_content).context("failed to import config")?;
+
+    if let Some(kotlin) = &config.kotlin {
+        config.build.get_or_insert_with(BuildConfig::default).kotlin = Some(kotlin.clone());
+    }
+
+    // A [build] table only needs the settings that differ from the defaults
+    if let Some(build) = &mut config.build {
+        let defaults = BuildConfig::default();
+        build.source_dir = build.source_dir.take().or(defaults.source_dir);
+        build.output_dir = build.output_dir.take().or(defaults.output_dir);
+        build.test_dir = build.test_dir.take().or(defaults.test_dir);
+        build.doc_dir = build.doc_dir.take().or(defaults.doc_dir);
+    }
+
+    if let Some(root) = workspace_root(dir) {
+        let default_output = BuildConfig::default().output_dir;
+        let build = config.build.get_or_insert_with(BuildConfig::default);
+        if build.output_dir.is_none() || build.output_dir == default_output {
+            let output_dir = root
+                .join("build")
+                .join(&config.project.name)
+                .join("classes");
+            build.output_dir = Some(output_dir.to_string_lossy().to_string());
+        }
+    }
 
     Ok(config)
 }
 
+/// The `[workspace]` table of the `Cup.toml` in `dir`, if it has one.
+pub fn load_workspace_at(dir: &Path) -> Result<Option<WorkspaceConfig>> {
+    let Ok(content) = fs::read_to_string(dir.join("Cup.toml")) else {
+        return Ok(None);
+    };
+    let manifest: WorkspaceManifest = toml::from_str(&content)
+        .context("failed to import config")
+        .map_err(Failure::config)?;
+    Ok(manifest.workspace)
+}
+
+/// Root of the workspace that lists the project in `dir` as a member.
+pub fn workspace_root(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    for candidate in dir.ancestors() {
+        let Ok(Some(workspace)) = load_workspace_at(candidate) else {
+            continue;
+        };
+        let is_member = workspace.members.iter().any(|member| {
+            candidate
+                .join(member)
+                .canonicalize()
+                .is_ok_and(|m| m == dir)
+        });
+        if is_member {
+            return Some(candidate.to_path_buf());
+        }
+    }
+    None
+}
+
+/// `Cup.lock` of the project, shared by all members of a workspace.
+pub fn lockfile_path() -> PathBuf {
+    workspace_root(Path::new("."))
+        .map(|root| root.join(LOCKFILE))
+        .unwrap_or_else(|| PathBuf::from(LOCKFILE))
+}
+
+/// Reads `Cup.toml` as an editable document that keeps comments and formatting.
+pub fn load_manifest() -> Result<DocumentMut> {
+    let content = fs::read_to_string("Cup.toml")
+        .context("Failed to read Cup.toml. Make sure it exists in the project root.")
+        .map_err(Failure::config)?;
+    content
+        .parse()
+        .context("failed to parse Cup.toml")
+        .map_err(Failure::config)
+}
+
+pub fn save_manifest(manifest: &DocumentMut) -> Result<()> {
+    fs::write("Cup.toml", manifest.to_string()).context("Failed to write Cup.toml")
+}
+
 // Keep the old function for backward compatibility with doc.rs
 pub fn discover_java_files(build_config: &BuildConfig) -> Result<Vec<PathBuf>> {
     crate::commands::build::discover_source_files(build_config)
 }
 
-pub fn collect_java_files(dir: &Path, acc: &mut Vec<PathBuf>) -> Result<()> {
-    crate::commands::build::collect_source_files(dir, acc)
+/// Cup's per-user directory, `$CUP_HOME` or `~/.cup`.
+pub fn cup_home() -> Result<PathBuf> {
+    if let Some(home) = std::env::var_os("CUP_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .context("Could not determine the home directory. Set CUP_HOME.")?;
+    Ok(PathBuf::from(home).join(".cup"))
 }
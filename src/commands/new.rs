@@ -1,13 +1,16 @@
 use std::{io::Write, process::Command};
 
-pub fn new_project(project_name: String, location: Option<String>, kotlin: bool) {
+use anyhow::{Context, Result};
+
+pub fn new_project(project_name: String, location: Option<String>, kotlin: bool) -> Result<()> {
     let full_path;
     if let Some(loc) = location {
         full_path = format!("{}/{}", loc, project_name);
     } else {
         full_path = project_name.to_string();
     }
-    let _ = create_project_structure(full_path, project_name, kotlin);
+    create_project_structure(full_path.clone(), project_name, kotlin)
+        .with_context(|| format!("Failed to create project in {full_path}"))
 }
 
 fn create_project_structure(
@@ -66,7 +69,7 @@ main_class = \"main.{}\"
 source_dir = \"src/main\"        # Optional: defaults to this
 output_dir = \"build/classes\"    # Optional: defaults to this  
 test_dir = \"src/test\"           # Optional: for future testing support
-java_version = \"11\"             # Optional: for future version checking
+java_version = \"11\"             # Optional: passed as --release, needs this JDK or newer
 doc_dir = \"doc\"                 # Optional: defaults to this
 
 [dependencies]
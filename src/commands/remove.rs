@@ -0,0 +1,55 @@
+use anyhow::{Result, bail};
+use toml_edit::Item;
+
+use crate::commands::{config_from_manifest, load_manifest, save_manifest};
+use crate::deps::{ResolveOptions, resolve_dependencies};
+
+/// Removes a dependency from `Cup.toml` by its name, artifact id or
+/// `group:artifact`, and updates `Cup.lock`.
+pub fn remove_dependency(name: String, options: &ResolveOptions) -> Result<()> {
+    let mut manifest = load_manifest()?;
+    let mut removed = vec![];
+
+    for section in ["dependencies", "dev-dependencies", "annotation-processors"] {
+        let Some(table) = manifest.get_mut(section).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        let keys: Vec<String> = table
+            .iter()
+            .filter(|(key, item)| *key == name || matches_coordinates(item, &name))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        for key in keys {
+            table.remove(&key);
+            removed.push(format!("{key} from [{section}]"));
+        }
+    }
+
+    if removed.is_empty() {
+        bail!("'{name}' is not a dependency of this project");
+    }
+    // Resolve before saving, so a failed resolution leaves Cup.toml and Cup.lock in step
+    resolve_dependencies(&config_from_manifest(&manifest)?, options)?;
+    save_manifest(&manifest)?;
+    for entry in removed {
+        println!("Removed {entry}");
+    }
+    Ok(())
+}
+
+/// Whether `item` declares the artifact `name`, given as `artifact` or `group:artifact`.
+fn matches_coordinates(item: &Item, name: &str) -> bool {
+    let coordinates = item.as_str().or_else(|| {
+        item.as_table_like()
+            .and_then(|t| t.get("coordinates"))
+            .and_then(Item::as_str)
+    });
+    let Some(coordinates) = coordinates else {
+        return false;
+    };
+    let mut parts = coordinates.split(':');
+    let (Some(group), Some(artifact)) = (parts.next(), parts.next()) else {
+        return false;
+    };
+    name == artifact || name == format!("{group}:{artifact}")
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use dialoguer::console::style;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::build::cache_dir;
+use crate::commands::{BuildConfig, ProjectConfig, ResourcesConfig};
+use crate::incremental::{files_under, path_stamp, sha256_hex};
+use crate::report::{self, Event};
+
+/// What was copied by the last build, stored under `build/.cup`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CopiedResources {
+    /// Hash of the filter values the files were copied with
+    filter: String,
+    /// Stamp of each resource when it was copied, by path relative to the resource directory
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+/// Resources of a source set such as `src/main`.
+pub fn resource_dir(source_dir: &str) -> PathBuf {
+    Path::new(source_dir).join("resources")
+}
+
+/// Copies `<source_dir>/resources` into the output directory.
+pub fn copy_main_resources(
+    build_config: &BuildConfig,
+    project: &ProjectConfig,
+    resources: Option<&ResourcesConfig>,
+) -> Result<()> {
+    process_resources(
+        &resource_dir(build_config.source_dir.as_ref().unwrap()),
+        Path::new(build_config.output_dir.as_ref().unwrap()),
+        &cache_dir(build_config).join("resources.toml"),
+        project,
+        resources,
+    )
+}
+
+/// Copies new and changed files from `resource_dir` to `output_dir` and deletes the
+/// copies of removed ones. `state` remembers what was copied.
+pub fn process_resources(
+    resource_dir: &Path,
+    output_dir: &Path,
+    state: &Path,
+    project: &ProjectConfig,
+    resources: Option<&ResourcesConfig>,
+) -> Result<()> {
+    let mut copied: CopiedResources = fs::read_to_string(state)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default();
+    let files = files_under(resource_dir)?;
+    if files.is_empty() && copied.files.is_empty() {
+        return Ok(());
+    }
+
+    let values = filter_values(project, resources);
+    let filter = values
+        .as_ref()
+        .map(|values| {
+            let lines: Vec<String> = values.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            sha256_hex(lines.join("\n").as_bytes())
+        })
+        .unwrap_or_default();
+    if copied.filter != filter {
+        copied.files.clear();
+        copied.filter = filter;
+    }
+
+    let mut current = BTreeMap::new();
+    let mut written = vec![];
+    for file in &files {
+        let relative = file
+            .strip_prefix(resource_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let stamp = path_stamp(file);
+        let dest = output_dir.join(&relative);
+        if copied.files.get(&relative) != Some(&stamp) || !dest.exists() {
+            copy_resource(file, &dest, values.as_ref())?;
+            written.push(relative.clone());
+        }
+        current.insert(relative, stamp);
+    }
+
+    for relative in copied.files.keys() {
+        if !current.contains_key(relative) {
+            let dest = output_dir.join(relative);
+            if dest.exists() {
+                fs::remove_file(&dest)
+                    .with_context(|| format!("Failed to delete {}", dest.display()))?;
+            }
+        }
+    }
+
+    if !written.is_empty() {
+        report::status(format_args!(
+            "    {}",
+            style(format!(
+                "Copied {} resources from {}",
+                written.len(),
+                resource_dir.display()
+            ))
+            .dim()
+        ));
+        report::emit(Event::ArtifactProduced {
+            kind: "resources",
+            path: output_dir,
+            files: written,
+            fresh: false,
+        });
+    }
+
+    copied.files = current;
+    if let Some(parent) = state.parent() {
+        fs::create_dir_all(parent).context("Failed to create build cache directory")?;
+    }
+    let content = toml::to_string(&copied).context("Failed to serialize resource state")?;
+    fs::write(state, content).with_context(|| format!("Failed to write {}", state.display()))
+}
+
+/// The `${...}` replacements, or `None` when filtering is off.
+fn filter_values(
+    project: &ProjectConfig,
+    resources: Option<&ResourcesConfig>,
+) -> Option<BTreeMap<String, String>> {
+    let resources = resources.filter(|r| r.filter == Some(true))?;
+    let mut values = BTreeMap::new();
+    values.insert("project.name".to_string(), project.name.clone());
+    values.insert("project.version".to_string(), project.version.clone());
+    if let Some(properties) = &resources.properties {
+        values.extend(properties.clone());
+    }
+    Some(values)
+}
+
+/// Copies one resource, replacing `${name}` placeholders in text files when filtering.
+/// Binary files and unknown placeholders are left untouched.
+fn copy_resource(
+    source: &Path,
+    dest: &Path,
+    values: Option<&BTreeMap<String, String>>,
+) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let text = values.and_then(|values| {
+        let text = String::from_utf8(fs::read(source).ok()?).ok()?;
+        Some((text, values))
+    });
+    match text {
+        Some((mut text, values)) => {
+            for (name, value) in values {
+                text = text.replace(&format!("${{{name}}}"), value);
+            }
+            fs::write(dest, text)
+        }
+        None => fs::copy(source, dest).map(|_| ()),
+    }
+    .with_context(|| format!("Failed to copy {} to {}", source.display(), dest.display()))
+}
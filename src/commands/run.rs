@@ -1,145 +1,281 @@
 use anyhow::{Context, Result, bail};
-use merkle_hash::MerkleTree;
-use std::fs;
-use std::io::Read;
-use std::ops::Not;
-use std::process::{Command, ExitStatus};
+use dialoguer::console::style;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+};
 
-use crate::commands::build::{build_classpath, compile_sources, discover_source_files};
-use crate::commands::load_config;
+use crate::commands::build::{
+    build_classpath, compile_sources, discover_source_files, fingerprints_path,
+};
+use crate::commands::load_config_for;
+use crate::commands::resources::copy_main_resources;
+use crate::deps::{
+    Classpath, ResolveOptions, ResolvedArtifact, build_local_dependencies, on_classpath,
+    resolve_dependencies,
+};
 
-use super::BuildConfig;
+use super::{BuildConfig, RunConfig};
+use crate::failure::Failure;
+use crate::incremental::{Fingerprints, source_key};
+use crate::report::{self, Event};
+use crate::toolchain::Toolchain;
 
-pub fn run_project() -> Result<()> {
-    let config = load_config()?;
+pub fn run_project(
+    profile: &str,
+    args: &[String],
+    overrides: RunConfig,
+    options: &ResolveOptions,
+) -> Result<()> {
+    let config = load_config_for(profile)?;
+    let toolchain = Toolchain::select(&config)?;
+    report::emit(Event::BuildStarted {
+        command: "run",
+        project: &config.project.name,
+        version: &config.project.version,
+        profile,
+    });
+    let mut dependencies = resolve_dependencies(&config, options)?;
+    dependencies.extend(build_local_dependencies(&config, profile, options)?);
 
     let build_config = config.build.unwrap_or_default();
-    // if let Ok(mut file) = std::fs::File::open("Cup.lock") {
-    //     let tree = MerkleTree::builder("src/")
-    //         .algorithm(merkle_hash::Algorithm::Blake3)
-    //         .hash_names(false)
-    //         .build()?;
-    //     let mut buf = vec![];
-    //     let _ = file.read_to_end(&mut buf);
-    //     dbg!(&buf);
-    //     dbg!(&tree.root.item.hash);
-
-    //     if tree.root.item.hash == buf {
-    //         if let Some(main_class) = &config.project.main_class {
-    //             println!("Running main class: {}", main_class);
-    //             run_main_class(main_class, &build_config)?;
-    //         } else {
-    //             println!("No main class specified in Cup.toml. Compilation complete.");
-    //         }
-    //     }
-    // } else {
     let source_files = discover_source_files(&build_config)?;
     if source_files.is_empty() {
         bail!("No source files found to compile");
     }
 
-    // println!("Found {} source files to compile", source_files.len());
-
     let output_dir = build_config.output_dir.as_ref().unwrap();
     fs::create_dir_all(output_dir).context("Failed to create output directory")?;
 
     // Compile the project
-    compile_sources(&source_files, &build_config)?;
-
-    if let Some(main_class) = &config.project.main_class {
-        println!("Running main class: {}", main_class);
-        run_main_class(main_class, &build_config)?;
-    } else {
-        println!("No main class specified in Cup.toml. Compilation complete.");
-        // }
+    compile_sources(&source_files, &build_config, &toolchain, &dependencies)?;
+    copy_main_resources(&build_config, &config.project, config.resources.as_ref())?;
+
+    let run_config = config.run.clone().unwrap_or_default().merge(overrides);
+    let Some(main_class) = &config.project.main_class else {
+        report::status(format_args!(
+            "No main class specified in Cup.toml. Compilation complete."
+        ));
+        return Ok(());
+    };
+
+    // Output that doesn't match the fingerprints, e.g. classes deleted by hand or a build
+    // interrupted halfway, gets one full rebuild before giving up
+    let mut rebuilt = false;
+    let mut missing_class = None;
+    let status = loop {
+        if main_class_file(&build_config, main_class).exists() {
+            report::status(format_args!("Running main class: {}", main_class));
+            let (status, missing) = run_main_class(
+                main_class,
+                args,
+                &run_config,
+                &build_config,
+                &toolchain,
+                &dependencies,
+                !rebuilt,
+            )?;
+            // Only the project's own classes can be fixed by compiling again
+            match missing {
+                Some(class)
+                    if !status.success()
+                        && !is_project_class(&build_config, &source_files, &class) =>
+                {
+                    missing_class = Some(class);
+                    break status;
+                }
+                Some(_) if !status.success() => {}
+                _ => break status,
+            }
+        } else if rebuilt {
+            return Err(Failure::Config(format!(
+                "Main class {main_class} was not found in {output_dir}. Check main_class in Cup.toml."
+            ))
+            .into());
+        }
+        report::status(format_args!(
+            "    {}",
+            style("Compiled classes are out of date, rebuilding everything...").dim()
+        ));
+        let _ = fs::remove_file(fingerprints_path(&build_config));
+        compile_sources(&source_files, &build_config, &toolchain, &dependencies)?;
+        rebuilt = true;
+    };
+    if !status.success() {
+        let mut message = format!("{main_class} didn't exit successfully");
+        if let Some(class) = missing_class {
+            message.push_str(&format!(
+                ", class {class} is not on the classpath. Is a dependency missing from Cup.toml?"
+            ));
+        }
+        return Err(Failure::exited(status, message).into());
     }
 
     Ok(())
 }
 
-fn run_main_class(main_class: &str, build_config: &BuildConfig) -> Result<()> {
-    let output_dir = build_config.output_dir.as_ref().unwrap();
+/// The class file `main_class` is compiled to.
+fn main_class_file(build_config: &BuildConfig, main_class: &str) -> PathBuf {
+    let output_dir = Path::new(build_config.output_dir.as_ref().unwrap());
+    output_dir.join(format!("{}.class", main_class.replace('.', "/")))
+}
 
-    // Check if this is a Kotlin project by looking for Kotlin files
-    let has_kotlin = check_for_kotlin_files(build_config)?;
+/// Whether `class`, e.g. `com/example/Helper`, is compiled from the project's sources:
+/// its class file is in the output directory, the last build recorded it, or a source
+/// file has its path.
+fn is_project_class(build_config: &BuildConfig, source_files: &[PathBuf], class: &str) -> bool {
+    let file = format!("{class}.class");
+    let output_dir = Path::new(build_config.output_dir.as_ref().unwrap());
+    if output_dir.join(&file).exists()
+        || Fingerprints::load(&fingerprints_path(build_config))
+            .sources
+            .values()
+            .any(|source| source.classes.contains(&file))
+    {
+        return true;
+    }
+    // Nested classes are in the file of the outer class, and Kotlin's `MainKt` in `Main.kt`
+    let outer = class.split('$').next().unwrap_or(class);
+    let mut names = vec![format!("/{outer}.java"), format!("/{outer}.kt")];
+    if let Some(kotlin) = outer.strip_suffix("Kt") {
+        names.push(format!("/{kotlin}.kt"));
+    }
+    source_files
+        .iter()
+        .map(|source| source_key(source))
+        .any(|source| names.iter().any(|name| source.ends_with(name)))
+}
 
-    let mut cmd = Command::new("java");
+/// Runs the program. With `watch_stderr`, its stderr is passed through a pipe to return
+/// the class of a `NoClassDefFoundError`, which may mean the compiled classes are stale.
+fn run_main_class(
+    main_class: &str,
+    args: &[String],
+    run_config: &RunConfig,
+    build_config: &BuildConfig,
+    toolchain: &Toolchain,
+    dependencies: &[ResolvedArtifact],
+    watch_stderr: bool,
+) -> Result<(ExitStatus, Option<String>)> {
+    let output_dir = build_config.output_dir.as_ref().unwrap();
+
+    let mut cmd = toolchain.java();
 
     // Build classpath
     let mut classpath_parts = vec![output_dir.to_string()];
 
-    if let Some(lib_classpath) = build_classpath() {
+    if let Some(lib_classpath) =
+        build_classpath(&on_classpath(dependencies, Classpath::Runtime), toolchain)
+    {
         classpath_parts.push(lib_classpath);
     }
 
-    // For Kotlin projects, we need to ensure Kotlin runtime is available
-    if has_kotlin {
-        add_kotlin_runtime_to_classpath(&mut classpath_parts);
+    // Absolute, so the classpath still works from another working directory
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    let full_classpath = classpath_parts
+        .join(separator)
+        .split(separator)
+        .map(|entry| {
+            std::path::absolute(entry)
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_else(|_| entry.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(separator);
+
+    let mut java_args: Vec<String> = run_config.jvm_args.iter().flatten().cloned().collect();
+    for (name, value) in run_config.system_properties.iter().flatten() {
+        java_args.push(format!("-D{name}={value}"));
+    }
+    java_args.extend(["-cp".to_string(), full_classpath, main_class.to_string()]);
+    java_args.extend(args.iter().cloned());
+    cmd.args(&java_args);
+    cmd.envs(run_config.env.iter().flatten());
+    if let Some(working_dir) = &run_config.working_dir {
+        if !Path::new(working_dir).is_dir() {
+            return Err(
+                Failure::Config(format!("Working directory {working_dir} does not exist")).into(),
+            );
+        }
+        cmd.current_dir(working_dir);
     }
 
-    let full_classpath = classpath_parts.join(if cfg!(windows) { ";" } else { ":" });
-    cmd.arg("-cp").arg(&full_classpath);
+    // stdout only has events with JSON output
+    if report::is_json() {
+        cmd.stdout(io::stderr());
+    }
 
-    cmd.arg(main_class);
+    report::status(format_args!("Executing: java {}", java_args.join(" ")));
 
-    println!("Executing: java -cp {} {}", full_classpath, main_class);
+    // Piped, the program no longer sees a terminal on stderr, so only when it matters
+    if watch_stderr {
+        cmd.stderr(Stdio::piped());
+    }
+    let mut child = cmd
+        .spawn()
+        .context("Failed to execute java. Make sure Java runtime is installed.")
+        .map_err(|e| Failure::Run(format!("{e:#}")))?;
 
-    let _ = cmd
-        .status()
-        .inspect(|s| {
-            if s.code() == Some(1) {
-                let _ = std::fs::remove_file("Cup.lock");
-                let _ = run_project();
+    let mut missing_class = None;
+    if let Some(stderr) = child.stderr.take() {
+        let mut reader = BufReader::new(stderr);
+        let mut line = vec![];
+        while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            if missing_class.is_none() {
+                missing_class = no_class_def_found(&String::from_utf8_lossy(&line));
             }
-        })
-        .context("Failed to execute java. Make sure Java runtime is installed.")?;
+            let mut out = io::stderr();
+            let _ = out.write_all(&line).and_then(|_| out.flush());
+            line.clear();
+        }
+    }
+    let status = child
+        .wait()
+        .context("Failed to wait for java")
+        .map_err(|e| Failure::Run(format!("{e:#}")))?;
 
-    Ok(())
+    Ok((status, missing_class))
 }
 
-fn check_for_kotlin_files(build_config: &BuildConfig) -> Result<bool> {
-    let source_files = discover_source_files(build_config)?;
-    Ok(source_files
-        .iter()
-        .any(|f| f.extension().is_some_and(|ext| ext == "kt")))
+/// The class in `java.lang.NoClassDefFoundError: com/example/Helper`. Classes whose
+/// static initializer failed (`Could not initialize class ...`) are not missing.
+fn no_class_def_found(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("NoClassDefFoundError: ")?;
+    if rest.starts_with("Could not initialize class") {
+        return None;
+    }
+    Some(rest.split_whitespace().next()?.replace('.', "/"))
 }
 
-fn add_kotlin_runtime_to_classpath(classpath_parts: &mut Vec<String>) {
-    // common locations of kotlin stdlib
-    let kotlin_runtime_paths = [
-        "/usr/share/kotlin/lib/kotlin-stdlib.jar",
-        "/opt/kotlin/lib/kotlin-stdlib.jar",
-        "~/.kotlinc/lib/kotlin-stdlib.jar",
-        "/usr/local/share/kotlin/lib/kotlin-stdlib.jar",
-        // Windows
-        "C:\\Program Files\\Kotlin\\lib\\kotlin-stdlib.jar",
-        "C:\\kotlin\\lib\\kotlin-stdlib.jar",
-        //mac should be the linux ones idk idc
-    ];
-
-    for path in &kotlin_runtime_paths {
-        let expanded_path = if path.starts_with('~') {
-            if let Some(home) = std::env::var_os("HOME") {
-                path.replace('~', &home.to_string_lossy())
-            } else {
-                path.to_string()
-            }
-        } else {
-            path.to_string()
-        };
-
-        if std::path::Path::new(&expanded_path).exists() {
-            classpath_parts.push(expanded_path);
-            return;
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Ok(output) = Command::new("kotlinc").arg("-cp").output()
-        && output.status.success()
-    {
-        let classpath = String::from_utf8_lossy(&output.stdout);
-        if !classpath.trim().is_empty() {
-            classpath_parts.push(classpath.trim().to_string());
-        }
+    #[test]
+    fn reads_the_missing_class() {
+        assert_eq!(
+            no_class_def_found(
+                "Exception in thread \"main\" java.lang.NoClassDefFoundError: com/example/Helper\n"
+            )
+            .as_deref(),
+            Some("com/example/Helper")
+        );
+        assert_eq!(
+            no_class_def_found("Caused by: java.lang.NoClassDefFoundError: com.example.Main$Inner")
+                .as_deref(),
+            Some("com/example/Main$Inner")
+        );
+        assert_eq!(
+            no_class_def_found(
+                "java.lang.NoClassDefFoundError: Could not initialize class com.example.Config"
+            ),
+            None
+        );
+        assert_eq!(
+            no_class_def_found("Caused by: java.lang.ClassNotFoundException: main.Helper"),
+            None
+        );
     }
 }
@@ -0,0 +1,141 @@
+use std::{fs, io, path::Path};
+
+use anyhow::{Context, Result, bail};
+
+use crate::commands::build::{
+    annotation_processing, build_classpath, cache_dir, compile_files, compile_sources,
+    discover_source_files, discover_sources_in, test_output_dir,
+};
+use crate::commands::resources::{copy_main_resources, process_resources, resource_dir};
+use crate::commands::workspace::{in_workspace_build, run_in_members};
+use crate::commands::{load_config_for, load_workspace_at};
+use crate::deps::{
+    Classpath, ResolveOptions, build_local_dependencies, on_classpath, resolve_dependencies,
+};
+use crate::failure::Failure;
+use crate::incremental::files_under;
+use crate::report::{self, Event};
+use crate::toolchain::Toolchain;
+
+const CONSOLE_LAUNCHER: &str = "org.junit.platform.console.ConsoleLauncher";
+
+pub fn test_project(profile: &str, options: &ResolveOptions) -> Result<()> {
+    if !in_workspace_build()
+        && let Some(workspace) = load_workspace_at(Path::new("."))?
+    {
+        return run_in_members(Path::new("."), &workspace, "test", profile, options);
+    }
+
+    let config = load_config_for(profile)?;
+    let toolchain = Toolchain::select(&config)?;
+    report::emit(Event::BuildStarted {
+        command: "test",
+        project: &config.project.name,
+        version: &config.project.version,
+        profile,
+    });
+    let mut dependencies = resolve_dependencies(&config, options)?;
+    dependencies.extend(build_local_dependencies(&config, profile, options)?);
+
+    let build_config = config.build.unwrap_or_default();
+    let output_dir = build_config.output_dir.as_ref().unwrap();
+
+    let source_files = discover_source_files(&build_config)?;
+    if !source_files.is_empty() {
+        fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+        compile_sources(&source_files, &build_config, &toolchain, &dependencies)?;
+    }
+    copy_main_resources(&build_config, &config.project, config.resources.as_ref())?;
+
+    let test_dir = build_config.test_dir.as_ref().unwrap();
+    let test_files = discover_sources_in(test_dir)?;
+    if test_files.is_empty() {
+        report::status(format_args!("No test sources found in {test_dir}"));
+        return Ok(());
+    }
+
+    let test_dependencies = on_classpath(&dependencies, Classpath::Test);
+    if !test_dependencies.iter().any(|d| {
+        d.coordinate.group == "org.junit.platform"
+            && d.coordinate.artifact.starts_with("junit-platform-console")
+    }) {
+        bail!(
+            "cup test runs tests with the JUnit Platform console launcher. Add it to Cup.toml:\n\n\
+             [dev-dependencies]\n\
+             junit = \"org.junit.platform:junit-platform-console-standalone:1.10.2\""
+        );
+    }
+
+    // Tests are always compiled from scratch so no stale test classes are picked up
+    let test_output = test_output_dir(&build_config);
+    let _ = fs::remove_dir_all(&test_output);
+    fs::create_dir_all(&test_output).context("Failed to create test output directory")?;
+    let test_output = test_output.to_string_lossy().to_string();
+
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    let mut classpath_parts = vec![output_dir.to_string()];
+    if let Some(cp) = build_classpath(&test_dependencies, &toolchain) {
+        classpath_parts.push(cp);
+    }
+
+    let processing = annotation_processing(&dependencies, &build_config, "test");
+    if let Some(processing) = &processing {
+        processing.clean()?;
+    }
+
+    report::status(format_args!("Compiling {} test files", test_files.len()));
+    compile_files(
+        &test_files,
+        &test_output,
+        &Some(classpath_parts.join(separator)),
+        processing.as_ref(),
+        &build_config,
+        &toolchain,
+    )?;
+    for file in &test_files {
+        report::emit(Event::FileCompiled { file });
+    }
+    if report::is_json() {
+        let classes = files_under(Path::new(&test_output))?
+            .iter()
+            .filter(|f| f.extension().is_some_and(|e| e == "class"))
+            .filter_map(|f| f.strip_prefix(&test_output).ok())
+            .map(|f| f.to_string_lossy().replace('\\', "/"))
+            .collect();
+        report::emit(Event::ArtifactProduced {
+            kind: "test-classes",
+            path: Path::new(&test_output),
+            files: classes,
+            fresh: false,
+        });
+    }
+    process_resources(
+        &resource_dir(test_dir),
+        Path::new(&test_output),
+        &cache_dir(&build_config).join("test-resources.toml"),
+        &config.project,
+        config.resources.as_ref(),
+    )?;
+
+    classpath_parts.insert(0, test_output.clone());
+    let mut cmd = toolchain.java();
+    cmd.arg("-cp")
+        .arg(classpath_parts.join(separator))
+        .arg(CONSOLE_LAUNCHER)
+        .arg("--scan-class-path")
+        .arg(&test_output);
+    // stdout only has events with JSON output
+    if report::is_json() {
+        cmd.stdout(io::stderr());
+    }
+    let status = cmd
+        .status()
+        .context("Failed to execute java. Make sure Java runtime is installed.")
+        .map_err(|e| Failure::Run(format!("{e:#}")))?;
+
+    if !status.success() {
+        return Err(Failure::Tests("Tests failed".to_string()).into());
+    }
+
+    Ok(())
+}
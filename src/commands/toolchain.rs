@@ -0,0 +1,219 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+use dialoguer::console::style;
+
+use crate::toolchain::{
+    Installation, discover_jdks, discover_kotlin, find_jdk, find_kotlin, load_defaults, read_jdk,
+    read_kotlin, save_defaults, subdirectories, toolchains_dir,
+};
+
+/// Unpacks a JDK or Kotlin compiler archive into `~/.cup/toolchains`.
+pub fn add_toolchain(archive: PathBuf) -> Result<()> {
+    if !archive.is_file() {
+        bail!("{} does not exist", archive.display());
+    }
+    let dir = toolchains_dir()?;
+    let staging = dir.join(format!(".unpack-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+
+    let result = unpack(&archive, &staging).and_then(|_| install(&staging, &dir));
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn unpack(archive: &Path, dest: &Path) -> Result<()> {
+    let name = archive.to_string_lossy().to_lowercase();
+    let mut cmd = if name.ends_with(".zip") {
+        let mut cmd = Command::new("unzip");
+        cmd.arg("-q").arg(archive).arg("-d").arg(dest);
+        cmd
+    } else if [".tar.gz", ".tgz", ".tar.xz", ".tar.bz2", ".tar"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+    {
+        let mut cmd = Command::new("tar");
+        cmd.arg("-xf").arg(archive).arg("-C").arg(dest);
+        cmd
+    } else {
+        bail!(
+            "Unsupported archive {}. Use a .tar.gz, .tar.xz or .zip distribution.",
+            archive.display()
+        );
+    };
+
+    println!("Unpacking {}", archive.display());
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let output = cmd.output().with_context(|| {
+        format!("Failed to execute {program}. Make sure it is installed and in PATH.")
+    })?;
+    if !output.status.success() {
+        bail!(
+            "Failed to unpack {}:\n{}",
+            archive.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Moves the distribution out of `staging` once it is known to work.
+fn install(staging: &Path, dir: &Path) -> Result<()> {
+    let [top] = subdirectories(staging).try_into().map_err(|_| {
+        anyhow::anyhow!("Expected the archive to contain a single top-level directory")
+    })?;
+
+    let (kind, installation) = [
+        top.clone(),
+        top.join("Contents").join("Home"),
+        top.join("kotlinc"),
+    ]
+    .iter()
+    .find_map(|home| {
+        read_jdk(home)
+            .map(|jdk| ("jdk", jdk))
+            .or_else(|| read_kotlin(home).map(|kotlin| ("kotlin", kotlin)))
+    })
+    .context(
+        "Not a JDK or Kotlin compiler: found no `release` file next to bin/javac \
+         and no lib/kotlin-compiler.jar",
+    )?;
+    verify(kind, &installation)?;
+
+    let dest = dir.join(format!("{kind}-{}", installation.version));
+    if dest.exists() {
+        bail!(
+            "{kind} {} is already installed at {}",
+            installation.version,
+            dest.display()
+        );
+    }
+    fs::rename(&top, &dest).with_context(|| format!("Failed to move to {}", dest.display()))?;
+
+    println!(
+        "{} {kind} {} to {}",
+        style("Installed").green().bold(),
+        installation.version,
+        dest.display()
+    );
+    Ok(())
+}
+
+/// Runs the unpacked JDK, so one built for another platform is caught now rather than at build time.
+fn verify(kind: &str, installation: &Installation) -> Result<()> {
+    let bin = installation.home.join("bin");
+    if kind == "kotlin" {
+        let kotlinc = bin.join(if cfg!(windows) {
+            "kotlinc.bat"
+        } else {
+            "kotlinc"
+        });
+        if !kotlinc.is_file() {
+            bail!("The Kotlin distribution has no {}", kotlinc.display());
+        }
+        return Ok(());
+    }
+
+    let runs = Command::new(bin.join(format!("java{}", std::env::consts::EXE_SUFFIX)))
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if !runs {
+        bail!(
+            "The unpacked JDK {} does not run on this machine (`java -version` failed)",
+            installation.version
+        );
+    }
+    Ok(())
+}
+
+/// Lists every JDK and Kotlin compiler Cup can see, marking the defaults.
+pub fn list_toolchains() -> Result<()> {
+    let defaults = load_defaults()?;
+    let managed = toolchains_dir()?;
+    let managed = managed.canonicalize().unwrap_or(managed);
+
+    let jdks = discover_jdks();
+    let default_jdk = defaults
+        .java
+        .as_ref()
+        .and_then(|java| find_jdk(&jdks, java, defaults.vendor.as_deref()));
+    print_installations("JDKs", &jdks, default_jdk, &managed);
+
+    let compilers = discover_kotlin();
+    let default_kotlin = defaults
+        .kotlin
+        .as_ref()
+        .and_then(|kotlin| find_kotlin(&compilers, kotlin));
+    println!();
+    print_installations("Kotlin compilers", &compilers, default_kotlin, &managed);
+
+    Ok(())
+}
+
+fn print_installations(
+    what: &str,
+    installations: &[Installation],
+    default: Option<&Installation>,
+    managed: &Path,
+) {
+    println!("{}", style(what).bold());
+    if installations.is_empty() {
+        println!("  (none found)");
+    }
+    for installation in installations {
+        let is_default = default.is_some_and(|d| d.home == installation.home);
+        let marker = if is_default { "*" } else { " " };
+        let mut line = format!("{marker} {:<10}", installation.version);
+        if let Some(vendor) = &installation.vendor {
+            line.push_str(&format!(" {vendor:<16}"));
+        }
+        line.push_str(&format!(" {}", installation.home.display()));
+        if installation.home.starts_with(managed) {
+            line.push_str(&format!(" {}", style("(cup)").dim()));
+        }
+        if is_default {
+            println!("{}", style(line).green());
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+/// Sets the JDK (or with `kotlin`, the Kotlin compiler) projects use when they don't pick one.
+pub fn set_default_toolchain(version: String, kotlin: bool) -> Result<()> {
+    let mut defaults = load_defaults()?;
+
+    if kotlin {
+        let compilers = discover_kotlin();
+        let Some(installation) = find_kotlin(&compilers, &version) else {
+            bail!("No installed Kotlin compiler matches {version}. See `cup toolchain list`.");
+        };
+        println!(
+            "Default Kotlin compiler is now {version} ({})",
+            installation.home.display()
+        );
+        defaults.kotlin = Some(version);
+    } else {
+        let jdks = discover_jdks();
+        let Some(installation) = find_jdk(&jdks, &version, None) else {
+            bail!("No installed JDK matches {version}. See `cup toolchain list`.");
+        };
+        println!(
+            "Default JDK is now {version} ({})",
+            installation.home.display()
+        );
+        defaults.java = Some(version);
+        defaults.vendor = None;
+    }
+
+    save_defaults(&defaults)
+}
@@ -0,0 +1,157 @@
+use anyhow::{Result, bail};
+use dialoguer::console::style;
+
+use crate::commands::{CupConfig, load_config};
+use crate::deps::{
+    Coordinate, Edge, Outcome, ResolveOptions, Resolver, Scope, declared_dependencies,
+    project_repositories, resolve_dependencies,
+};
+
+/// Prints the resolved dependency graph, or with `invert`, every path that
+/// leads to the matching artifact.
+pub fn print_tree(invert: Option<String>, options: &ResolveOptions) -> Result<()> {
+    let config = load_config()?;
+    let declared = declared_dependencies(&config, options)?;
+    // What the build uses: Cup.lock, re-resolved only when it is missing or stale
+    let resolved = resolve_dependencies(&config, options)?;
+
+    // The lockfile has no edges, so walk the POMs again with its versions
+    let repositories = project_repositories(&config, options)?;
+    let mut resolver = Resolver::new(repositories, options.offline)?;
+    resolver.pin(&resolved);
+    resolver.resolve(&declared)?;
+    let edges = resolver.edges();
+
+    if let Some(target) = invert {
+        let matches: Vec<&Coordinate> = resolved
+            .iter()
+            .map(|a| &a.coordinate)
+            .filter(|c| {
+                target == c.artifact
+                    || target == format!("{}:{}", c.group, c.artifact)
+                    || target == c.to_string()
+            })
+            .collect();
+        if matches.is_empty() {
+            bail!("no resolved dependency matches '{target}'");
+        }
+        for coordinate in matches {
+            println!("{}", style(coordinate).bold());
+            print_dependents(&config, edges, coordinate, "", &mut vec![]);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{} v{}",
+        style(&config.project.name).bold(),
+        config.project.version
+    );
+    let mut duplicates = false;
+    for scope in [
+        Scope::Compile,
+        Scope::Provided,
+        Scope::Runtime,
+        Scope::Test,
+        Scope::Processor,
+    ] {
+        let roots: Vec<&Edge> = edges
+            .iter()
+            .filter(|e| e.parent.is_none() && e.scope == scope)
+            .collect();
+        if roots.is_empty() {
+            continue;
+        }
+        println!("\n{}", style(format!("[{scope}]")).cyan());
+        print_edges(edges, &roots, scope, "", &mut duplicates);
+    }
+    if duplicates {
+        println!("\n(*) already listed above, not repeated");
+    }
+
+    Ok(())
+}
+
+fn print_edges(edges: &[Edge], level: &[&Edge], scope: Scope, prefix: &str, dup: &mut bool) {
+    for (i, edge) in level.iter().enumerate() {
+        let last = i + 1 == level.len();
+        let mut line = edge.requested.to_string();
+        if edge.scope != scope {
+            line.push_str(&format!(" [{}]", edge.scope));
+        }
+        match &edge.outcome {
+            Outcome::Selected => println!("{prefix}{}{line}", branch(last)),
+            Outcome::Duplicate => {
+                *dup = true;
+                println!("{prefix}{}{line} {}", branch(last), style("(*)").dim());
+            }
+            Outcome::Conflict(winner) => println!(
+                "{prefix}{}{} {}",
+                branch(last),
+                style(line).dim(),
+                style(format!("(omitted for conflict with {winner})")).yellow()
+            ),
+        }
+
+        if edge.outcome == Outcome::Selected {
+            let children: Vec<&Edge> = edges
+                .iter()
+                .filter(|e| e.parent.as_ref() == Some(&edge.requested))
+                .collect();
+            let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+            print_edges(edges, &children, scope, &prefix, dup);
+        }
+    }
+}
+
+/// Walks the graph upwards from `coordinate` to the dependencies declared in `Cup.toml`.
+fn print_dependents(
+    config: &CupConfig,
+    edges: &[Edge],
+    coordinate: &Coordinate,
+    prefix: &str,
+    path: &mut Vec<Coordinate>,
+) {
+    let incoming: Vec<&Edge> = edges
+        .iter()
+        .filter(|e| {
+            e.requested.group == coordinate.group && e.requested.artifact == coordinate.artifact
+        })
+        .collect();
+
+    path.push(coordinate.clone());
+    for (i, edge) in incoming.iter().enumerate() {
+        let last = i + 1 == incoming.len();
+        let note = match &edge.outcome {
+            Outcome::Conflict(winner) => style(format!(
+                " (requests {}, mediated to {winner})",
+                edge.requested.version
+            ))
+            .yellow()
+            .to_string(),
+            _ => String::new(),
+        };
+
+        match &edge.parent {
+            None => println!(
+                "{prefix}{}{} (Cup.toml, {}){note}",
+                branch(last),
+                config.project.name,
+                edge.scope
+            ),
+            Some(parent) if path.contains(parent) => {
+                println!("{prefix}{}{parent} (cycle){note}", branch(last))
+            }
+            Some(parent) => {
+                println!("{prefix}{}{parent}{note}", branch(last));
+                let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                print_dependents(config, edges, parent, &prefix, path);
+            }
+        }
+    }
+    path.pop();
+}
+
+fn branch(last: bool) -> &'static str {
+    if last { "└── " } else { "├── " }
+}
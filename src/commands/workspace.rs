@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use dialoguer::console::style;
+
+use crate::commands::{CupConfig, DependencySpec, WorkspaceConfig, load_config_at};
+use crate::deps::ResolveOptions;
+use crate::failure::Failure;
+use crate::report;
+
+/// Set for the `cup` processes a workspace build spawns for its members.
+pub const WORKSPACE_BUILD_ENV: &str = "CUP_WORKSPACE_BUILD";
+
+/// Whether this process was started by a workspace build, which already
+/// builds every member in dependency order.
+pub fn in_workspace_build() -> bool {
+    std::env::var_os(WORKSPACE_BUILD_ENV).is_some()
+}
+
+pub struct Member {
+    pub dir: PathBuf,
+    pub config: CupConfig,
+}
+
+/// Runs `cup <command> --profile <profile>` in every member of the workspace, dependencies first.
+pub fn run_in_members(
+    root: &Path,
+    workspace: &WorkspaceConfig,
+    command: &str,
+    profile: &str,
+    options: &ResolveOptions,
+) -> Result<()> {
+    let members = ordered_members(root, workspace)?;
+    for member in &members {
+        report::status(format_args!(
+            "{} cup {command} in {} ({})",
+            style("Workspace").green().bold(),
+            member.config.project.name,
+            member.dir.display()
+        ));
+        let mut cmd = Command::new(std::env::current_exe()?);
+        cmd.arg(command)
+            .arg("--profile")
+            .arg(profile)
+            .current_dir(&member.dir)
+            .env(WORKSPACE_BUILD_ENV, "1");
+        if options.offline {
+            cmd.arg("--offline");
+        }
+        if options.locked {
+            cmd.arg("--locked");
+        }
+        report::forward_to(&mut cmd);
+        let status = cmd
+            .status()
+            .with_context(|| format!("Failed to run cup {command} in {}", member.dir.display()))?;
+        if !status.success() {
+            return Err(Failure::exited(
+                status,
+                format!(
+                    "cup {command} failed for workspace member '{}'",
+                    member.config.project.name
+                ),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Loads the members and sorts them so that every member comes after the
+/// members it depends on through `path` dependencies.
+pub fn ordered_members(root: &Path, workspace: &WorkspaceConfig) -> Result<Vec<Member>> {
+    let mut members = vec![];
+    for path in &workspace.members {
+        let dir = root
+            .join(path)
+            .canonicalize()
+            .with_context(|| format!("workspace member '{path}' not found"))?;
+        let config = load_config_at(&dir)
+            .with_context(|| format!("failed to load workspace member '{path}'"))?;
+        members.push(Member { dir, config });
+    }
+
+    let index: HashMap<PathBuf, usize> = members
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.dir.clone(), i))
+        .collect();
+    let mut dependencies: Vec<Vec<usize>> = vec![vec![]; members.len()];
+    for (i, member) in members.iter().enumerate() {
+        let tables = [&member.config.dependencies, &member.config.dev_dependencies];
+        for (_, spec) in tables.into_iter().flatten().flatten() {
+            if let DependencySpec::Detailed(detailed) = spec
+                && let Some(path) = &detailed.path
+                && let Ok(dir) = member.dir.join(path).canonicalize()
+                && let Some(&j) = index.get(&dir)
+            {
+                dependencies[i].push(j);
+            }
+        }
+    }
+
+    // Kahn's algorithm, keeping the order of `members` among independent members
+    let mut done = vec![false; members.len()];
+    let mut order = vec![];
+    while order.len() < members.len() {
+        let Some(next) =
+            (0..members.len()).find(|&i| !done[i] && dependencies[i].iter().all(|&j| done[j]))
+        else {
+            let cycle: Vec<String> = (0..members.len())
+                .filter(|&i| !done[i])
+                .map(|i| members[i].config.project.name.clone())
+                .collect();
+            bail!(
+                "workspace members depend on each other in a cycle: {}",
+                cycle.join(", ")
+            );
+        };
+        done[next] = true;
+        order.push(next);
+    }
+
+    let mut members: Vec<Option<Member>> = members.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|i| members[i].take().unwrap())
+        .collect())
+}
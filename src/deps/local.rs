@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result, bail};
+use dialoguer::console::style;
+use sha2::{Digest, Sha256};
+
+use super::lockfile::{LOCKFILE, Lockfile};
+use super::{Coordinate, ResolveOptions, ResolvedArtifact, Scope};
+use crate::commands::workspace::in_workspace_build;
+use crate::commands::{
+    BuildConfig, CupConfig, DependencySpec, cup_home, load_config_at, lockfile_path, workspace_root,
+};
+use crate::failure::Failure;
+use crate::report;
+
+/// Another Cup project used as a dependency, through `path` or `git`.
+pub struct LocalProject {
+    pub name: String,
+    pub dir: PathBuf,
+    pub config: CupConfig,
+    pub scope: Scope,
+    /// Declared by this project rather than by one of its path dependencies
+    pub direct: bool,
+}
+
+impl LocalProject {
+    pub fn output_dir(&self) -> PathBuf {
+        let output_dir = self
+            .config
+            .build
+            .as_ref()
+            .and_then(|b| b.output_dir.clone())
+            .or(BuildConfig::default().output_dir)
+            .unwrap();
+        self.dir.join(output_dir)
+    }
+}
+
+/// Every path and git dependency of `config`, including the ones they bring in
+/// themselves, dependencies first. Paths are relative to `base`, the directory of `config`.
+/// Their configs get `profile` applied, like the project that is built with them.
+pub fn local_projects(
+    config: &CupConfig,
+    base: &Path,
+    profile: &str,
+    options: &ResolveOptions,
+) -> Result<Vec<LocalProject>> {
+    let mut projects = vec![];
+    let base = base
+        .canonicalize()
+        .with_context(|| format!("Failed to find {}", base.display()))?;
+    let mut stack = vec![base.clone()];
+    collect(
+        config,
+        &base,
+        None,
+        &mut stack,
+        &mut projects,
+        profile,
+        options,
+    )?;
+    Ok(projects)
+}
+
+fn collect(
+    config: &CupConfig,
+    base: &Path,
+    parent_scope: Option<Scope>,
+    stack: &mut Vec<PathBuf>,
+    projects: &mut Vec<LocalProject>,
+    profile: &str,
+    options: &ResolveOptions,
+) -> Result<()> {
+    let tables = [
+        (&config.dependencies, Scope::Compile),
+        (&config.dev_dependencies, Scope::Test),
+    ];
+    for (table, default_scope) in tables {
+        for (name, spec) in table.iter().flatten() {
+            let DependencySpec::Detailed(detailed) = spec else {
+                continue;
+            };
+            let dir = match (&detailed.path, &detailed.git) {
+                (Some(path), None) => base.join(path),
+                (None, Some(url)) => checkout(name, url, detailed.rev.as_deref(), options)?,
+                _ => continue,
+            };
+            let dir = dir.canonicalize().with_context(|| {
+                format!("path dependency '{name}' not found at {}", dir.display())
+            })?;
+
+            let declared_scope = detailed.scope.unwrap_or(default_scope);
+            let scope = match parent_scope {
+                None => declared_scope,
+                Some(parent) => match parent.transitive(Some(&declared_scope.to_string())) {
+                    Some(scope) => scope,
+                    None => continue,
+                },
+            };
+
+            if stack.contains(&dir) {
+                bail!(
+                    "path dependency cycle: {} -> {}",
+                    stack
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                    dir.display()
+                );
+            }
+            if projects.iter().any(|p| p.dir == dir) {
+                continue;
+            }
+
+            let mut child = load_config_at(&dir)
+                .with_context(|| format!("path dependency '{name}' is not a Cup project"))?;
+            child
+                .apply_profile(profile)
+                .map_err(Failure::config)
+                .with_context(|| format!("path dependency '{name}' has no profile '{profile}'"))?;
+            stack.push(dir.clone());
+            collect(&child, &dir, Some(scope), stack, projects, profile, options)?;
+            projects.push(LocalProject {
+                name: name.clone(),
+                dir,
+                config: child,
+                scope,
+                direct: parent_scope.is_none(),
+            });
+            stack.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Builds the path and git dependencies of the project with their own
+/// `Cup.toml` and returns their output directories as classpath entries.
+pub fn build_local_dependencies(
+    config: &CupConfig,
+    profile: &str,
+    options: &ResolveOptions,
+) -> Result<Vec<ResolvedArtifact>> {
+    let mut artifacts = vec![];
+    for project in local_projects(config, Path::new("."), profile, options)? {
+        // Indirect path dependencies are built by the project that declares them,
+        // and workspace builds already build every member in dependency order
+        let workspace_member = in_workspace_build()
+            && workspace_root(&project.dir).is_some()
+            && workspace_root(&project.dir) == workspace_root(Path::new("."));
+        if project.direct && !workspace_member {
+            report::status(format_args!(
+                "{} {} ({})",
+                style("Building").green().bold(),
+                project.name,
+                project.dir.display()
+            ));
+            let mut cmd = Command::new(std::env::current_exe()?);
+            cmd.arg("build")
+                .arg("--profile")
+                .arg(profile)
+                .current_dir(&project.dir);
+            if options.offline {
+                cmd.arg("--offline");
+            }
+            if options.locked {
+                cmd.arg("--locked");
+            }
+            report::forward_to(&mut cmd);
+            let status = cmd
+                .status()
+                .with_context(|| format!("Failed to build path dependency '{}'", project.name))?;
+            if !status.success() {
+                return Err(Failure::exited(
+                    status,
+                    format!("Failed to build path dependency '{}'", project.name),
+                )
+                .into());
+            }
+        }
+
+        artifacts.push(ResolvedArtifact {
+            coordinate: Coordinate {
+                group: "path".to_string(),
+                artifact: project.config.project.name.clone(),
+                version: project.config.project.version.clone(),
+            },
+            scope: project.scope,
+            path: project.output_dir(),
+            repository: project.dir.display().to_string(),
+            checksum: String::new(),
+        });
+    }
+    Ok(artifacts)
+}
+
+/// Checkouts already made by this command, by URL and revision.
+static CHECKED_OUT: Mutex<BTreeMap<(String, String), PathBuf>> = Mutex::new(BTreeMap::new());
+
+/// Checks `rev` of a git repository out under `~/.cup/git` and returns the checkout.
+/// The commit is recorded in `Cup.lock`. Branches are fetched again unless the
+/// build is `--locked` or `--offline`, which stay on the locked commit.
+fn checkout(name: &str, url: &str, rev: Option<&str>, options: &ResolveOptions) -> Result<PathBuf> {
+    let requested = rev.unwrap_or("HEAD");
+    let memo = (url.to_string(), requested.to_string());
+    if let Some(dir) = CHECKED_OUT.lock().unwrap().get(&memo) {
+        return Ok(dir.clone());
+    }
+
+    let url_hash: String = Sha256::digest(url.as_bytes())
+        .iter()
+        .take(6)
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let dir = cup_home()?
+        .join("git")
+        .join(format!("{name}-{url_hash}"))
+        .join(requested.replace(['/', '\\'], "_"));
+
+    let lock_path = lockfile_path();
+    let mut lockfile = Lockfile::load(&lock_path).unwrap_or_default();
+    let locked = lockfile.git(url, requested).map(|g| g.commit.clone());
+    if options.locked && locked.is_none() {
+        bail!(
+            "{LOCKFILE} has no commit for git dependency '{name}' but --locked was passed; \
+             run the command without --locked to update it"
+        );
+    }
+
+    let mut fetched = false;
+    if !dir.join(".git").exists() {
+        if options.offline {
+            bail!(
+                "git dependency '{name}' ({url}) has not been checked out yet and Cup is offline"
+            );
+        }
+        report::status(format_args!(
+            "{} {url} ({requested})",
+            style("Checking out").green().bold()
+        ));
+        let parent = dir.parent().unwrap();
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+        git(parent, &["clone", "--quiet", url, &dir.to_string_lossy()])
+            .with_context(|| format!("Failed to clone git dependency '{name}' from {url}"))?;
+        fetched = true;
+    }
+    let has_commit = |commit: &str| {
+        git(
+            &dir,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{commit}^{{commit}}"),
+            ],
+        )
+        .ok()
+    };
+    let mut fetch = || -> Result<()> {
+        if options.offline {
+            bail!("git dependency '{name}' ({url}) needs a fetch, but Cup is offline");
+        }
+        if !fetched {
+            git(&dir, &["fetch", "--quiet", "--tags", "origin"])?;
+            fetched = true;
+        }
+        Ok(())
+    };
+
+    let pinned = requested.len() >= 7 && requested.chars().all(|c| c.is_ascii_hexdigit());
+    let commit = match locked {
+        Some(commit) if options.locked || options.offline || pinned => {
+            if has_commit(&commit).is_none() {
+                fetch()?;
+            }
+            has_commit(&commit)
+                .with_context(|| format!("commit {commit} of '{name}' not found in {url}"))?
+        }
+        _ => {
+            if !(pinned && has_commit(requested).is_some()) {
+                fetch()?;
+            }
+            // The remote branch first: local branches keep pointing where the clone left them
+            let candidates = match rev {
+                Some(rev) => vec![format!("origin/{rev}"), rev.to_string()],
+                None => vec!["origin/HEAD".to_string(), "HEAD".to_string()],
+            };
+            candidates
+                .iter()
+                .find_map(|candidate| has_commit(candidate))
+                .with_context(|| format!("revision '{requested}' not found in {url}"))?
+        }
+    };
+    git(&dir, &["checkout", "--quiet", "--detach", &commit])?;
+
+    if lockfile.update_git(url, requested, &commit) {
+        lockfile.save(&lock_path)?;
+    }
+    CHECKED_OUT.lock().unwrap().insert(memo, dir.clone());
+    Ok(dir)
+}
+
+/// Runs git in `dir` and returns what it printed.
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to execute git. Make sure git is installed and in PATH.")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Projects written into a temporary directory, removed again on drop.
+    struct TestProjects {
+        root: PathBuf,
+    }
+
+    impl TestProjects {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("cup-local-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            Self {
+                root: root.canonicalize().unwrap(),
+            }
+        }
+
+        fn write(&self, dir: &str, manifest: &str) -> PathBuf {
+            let dir = self.root.join(dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("Cup.toml"), manifest).unwrap();
+            dir
+        }
+
+        fn output_dirs(&self, dir: &Path, profile: &str) -> Vec<PathBuf> {
+            let config = load_config_at(dir).unwrap();
+            local_projects(&config, dir, profile, &ResolveOptions::default())
+                .unwrap()
+                .iter()
+                .map(LocalProject::output_dir)
+                .collect()
+        }
+    }
+
+    impl Drop for TestProjects {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn workspace_members_use_the_output_of_the_profile() {
+        let projects = TestProjects::new("workspace");
+        projects.write(".", "[workspace]\nmembers = [\"core\", \"app\"]\n");
+        projects.write("core", "[project]\nname = \"core\"\nversion = \"0.1.0\"\n");
+        let app = projects.write(
+            "app",
+            "[project]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\ncore = { path = \"../core\" }\n",
+        );
+
+        let build = projects.root.join("build").join("core");
+        assert_eq!(projects.output_dirs(&app, "dev"), [build.join("classes")]);
+        assert_eq!(
+            projects.output_dirs(&app, "release"),
+            [build.join("release").join("classes")]
+        );
+    }
+
+    #[test]
+    fn path_dependencies_use_the_output_of_the_profile() {
+        let projects = TestProjects::new("path");
+        let lib = projects.write(
+            "lib",
+            "[project]\nname = \"lib\"\nversion = \"0.1.0\"\n\n\
+             [profile.bench]\noutput_dir = \"out/bench\"\n",
+        );
+        let app = projects.write(
+            "app",
+            "[project]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nlib = { path = \"../lib\" }\n",
+        );
+
+        assert_eq!(
+            projects.output_dirs(&app, "release"),
+            [lib.join("build/release/classes")]
+        );
+        assert_eq!(projects.output_dirs(&app, "bench"), [lib.join("out/bench")]);
+    }
+}
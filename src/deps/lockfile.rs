@@ -0,0 +1,125 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const LOCKFILE: &str = "Cup.lock";
+
+/// Bumped when resolution changes, so older lockfiles are resolved again.
+/// 2: scopes of artifacts reached through several paths are widened.
+const VERSION: u32 = 2;
+
+const HEADER: &str = "# This file is generated by Cup. It is not meant to be edited by hand.\n\n";
+
+/// Contents of `Cup.lock`: what each project asked for and the exact
+/// artifacts that were resolved for it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Lockfile {
+    pub version: u32,
+    #[serde(default, rename = "project")]
+    pub projects: Vec<LockedProject>,
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+    #[serde(default)]
+    pub git: Vec<LockedGit>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockedProject {
+    pub name: String,
+    /// Dependencies as declared in `Cup.toml`
+    pub requires: Vec<String>,
+    /// Every resolved artifact as `group:artifact:version:scope`, in classpath order
+    pub resolved: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockedPackage {
+    pub id: String,
+    pub repository: String,
+    pub checksum: String,
+}
+
+/// The commit a git dependency was checked out at.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockedGit {
+    pub url: String,
+    /// `rev` from `Cup.toml`, or `HEAD` for the default branch
+    pub rev: String,
+    pub commit: String,
+}
+
+impl Lockfile {
+    /// Reads the lockfile, returning `None` if it is missing or not in the current format.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        toml::from_str::<Self>(&content)
+            .ok()
+            .filter(|lock| lock.version == VERSION)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize Cup.lock")?;
+        fs::write(path, format!("{HEADER}{content}"))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn project(&self, name: &str) -> Option<&LockedProject> {
+        self.projects.iter().find(|p| p.name == name)
+    }
+
+    pub fn package(&self, id: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.id == id)
+    }
+
+    pub fn git(&self, url: &str, rev: &str) -> Option<&LockedGit> {
+        self.git.iter().find(|g| g.url == url && g.rev == rev)
+    }
+
+    /// Records the commit of a git dependency. Returns whether it changed.
+    pub fn update_git(&mut self, url: &str, rev: &str, commit: &str) -> bool {
+        if self.git(url, rev).is_some_and(|g| g.commit == commit) {
+            return false;
+        }
+        self.version = VERSION;
+        self.git.retain(|g| g.url != url || g.rev != rev);
+        self.git.push(LockedGit {
+            url: url.to_string(),
+            rev: rev.to_string(),
+            commit: commit.to_string(),
+        });
+        self.git
+            .sort_by(|a, b| (&a.url, &a.rev).cmp(&(&b.url, &b.rev)));
+        true
+    }
+
+    /// Replaces the entry for `project` and drops packages no project uses anymore.
+    pub fn update(&mut self, project: LockedProject, packages: Vec<LockedPackage>) {
+        self.version = VERSION;
+        self.projects.retain(|p| p.name != project.name);
+        self.projects.push(project);
+        self.projects.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for package in packages {
+            self.packages.retain(|p| p.id != package.id);
+            self.packages.push(package);
+        }
+        let used: Vec<&str> = self
+            .projects
+            .iter()
+            .flat_map(|p| &p.resolved)
+            .map(|entry| entry.rsplit_once(':').map_or(entry.as_str(), |(id, _)| id))
+            .collect();
+        self.packages.retain(|p| used.contains(&p.id.as_str()));
+        self.packages.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Sha256::digest(&bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
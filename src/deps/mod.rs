@@ -0,0 +1,1008 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, bail};
+use dialoguer::console::style;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{CupConfig, DEFAULT_PROFILE, DependencySpec, lockfile_path};
+use crate::report;
+
+mod local;
+mod lockfile;
+mod pom;
+mod repository;
+
+pub use local::build_local_dependencies;
+pub use lockfile::{LOCKFILE, sha256_file};
+use lockfile::{LockedPackage, LockedProject, Lockfile};
+use pom::{Exclusion, Pom, pick_version};
+pub use repository::{Repository, expand_home};
+
+/// A Maven artifact in `group:artifact:version` form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Coordinate {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+}
+
+impl Coordinate {
+    /// Path of a file belonging to this artifact, relative to a Maven-layout repository root.
+    pub fn repository_path(&self, extension: &str) -> String {
+        format!(
+            "{}/{}/{}/{}-{}.{}",
+            self.group.replace('.', "/"),
+            self.artifact,
+            self.version,
+            self.artifact,
+            self.version,
+            extension
+        )
+    }
+}
+
+impl FromStr for Coordinate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.trim().split(':').collect();
+        match parts.as_slice() {
+            [group, artifact, version]
+                if !group.is_empty() && !artifact.is_empty() && !version.is_empty() =>
+            {
+                Ok(Self {
+                    group: group.to_string(),
+                    artifact: artifact.to_string(),
+                    version: version.to_string(),
+                })
+            }
+            _ => bail!("invalid Maven coordinate '{s}', expected \"group:artifact:version\""),
+        }
+    }
+}
+
+impl fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)
+    }
+}
+
+/// Where a dependency is needed, following Maven's scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Needed to compile and run the project
+    Compile,
+    /// Needed to compile, but supplied by the runtime environment
+    Provided,
+    /// Needed only to run the project
+    Runtime,
+    /// Needed only to compile and run the tests
+    Test,
+    /// Annotation processor run by javac, from `[annotation-processors]`
+    Processor,
+}
+
+impl Scope {
+    /// Scope of a transitive dependency declared with `declared` inside an artifact with this scope.
+    /// Returns `None` when the dependency does not propagate.
+    fn transitive(self, declared: Option<&str>) -> Option<Self> {
+        let declared = match declared {
+            None | Some("compile") => Self::Compile,
+            Some("runtime") => Self::Runtime,
+            _ => return None,
+        };
+        Some(match (self, declared) {
+            (Self::Compile, declared) => declared,
+            (Self::Runtime, _) => Self::Runtime,
+            (scope, _) => scope,
+        })
+    }
+
+    /// Scope of an artifact reached through paths with both scopes. Like Maven,
+    /// compile beats runtime, runtime beats provided and provided beats test.
+    fn widen(self, other: Self) -> Self {
+        let rank = |scope| match scope {
+            Self::Compile => 0,
+            Self::Runtime => 1,
+            Self::Provided => 2,
+            Self::Test => 3,
+            Self::Processor => 4,
+        };
+        if rank(other) < rank(self) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "compile" => Ok(Self::Compile),
+            "provided" => Ok(Self::Provided),
+            "runtime" => Ok(Self::Runtime),
+            "test" => Ok(Self::Test),
+            "processor" => Ok(Self::Processor),
+            _ => {
+                bail!("unknown scope '{s}', expected compile, provided, runtime, test or processor")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Compile => "compile",
+            Self::Provided => "provided",
+            Self::Runtime => "runtime",
+            Self::Test => "test",
+            Self::Processor => "processor",
+        })
+    }
+}
+
+/// The classpaths Cup builds, each made of a different set of scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classpath {
+    /// `javac`/`kotlinc` for the main sources
+    Compile,
+    /// `java` when running the main class
+    Runtime,
+    /// Compiling and running the tests
+    Test,
+    /// javac's `-processorpath`
+    Processor,
+}
+
+impl Classpath {
+    pub fn includes(self, scope: Scope) -> bool {
+        match self {
+            Self::Compile => matches!(scope, Scope::Compile | Scope::Provided),
+            Self::Runtime => matches!(scope, Scope::Compile | Scope::Runtime),
+            Self::Test => scope != Scope::Processor,
+            Self::Processor => scope == Scope::Processor,
+        }
+    }
+}
+
+/// A dependency whose jar is available in the local cache.
+#[derive(Debug, Clone)]
+pub struct ResolvedArtifact {
+    pub coordinate: Coordinate,
+    pub scope: Scope,
+    pub path: PathBuf,
+    pub repository: String,
+    pub checksum: String,
+}
+
+/// The artifacts of `dependencies` that belong on `classpath`.
+pub fn on_classpath(
+    dependencies: &[ResolvedArtifact],
+    classpath: Classpath,
+) -> Vec<ResolvedArtifact> {
+    dependencies
+        .iter()
+        .filter(|d| classpath.includes(d.scope))
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveOptions {
+    /// Fail instead of updating `Cup.lock` when it does not match `Cup.toml`
+    pub locked: bool,
+    /// Never touch the network, use only the local cache and `~/.m2/repository`
+    pub offline: bool,
+}
+
+/// A dependency as written in `Cup.toml`.
+#[derive(Debug, Clone)]
+pub struct Declared {
+    pub name: String,
+    pub coordinate: Coordinate,
+    pub scope: Scope,
+}
+
+/// Reads `[dependencies]`, `[dev-dependencies]` and `[annotation-processors]` into Maven coordinates, including
+/// the ones path and git dependencies need at compile time or runtime.
+pub fn declared_dependencies(
+    config: &CupConfig,
+    options: &ResolveOptions,
+) -> Result<Vec<Declared>> {
+    let mut declared = declared_maven(config)?;
+    for project in local::local_projects(config, Path::new("."), DEFAULT_PROFILE, options)? {
+        for dependency in declared_maven(&project.config)? {
+            let Some(scope) = project
+                .scope
+                .transitive(Some(&dependency.scope.to_string()))
+            else {
+                continue;
+            };
+            declared.push(Declared {
+                name: format!("{}/{}", project.name, dependency.name),
+                scope,
+                ..dependency
+            });
+        }
+    }
+    Ok(declared)
+}
+
+fn declared_maven(config: &CupConfig) -> Result<Vec<Declared>> {
+    let mut declared = vec![];
+    let tables = [
+        (&config.dependencies, Scope::Compile),
+        (&config.dev_dependencies, Scope::Test),
+        (&config.annotation_processors, Scope::Processor),
+    ];
+    for (table, default_scope) in tables {
+        for (name, spec) in table.iter().flatten() {
+            if default_scope == Scope::Processor
+                && let DependencySpec::Detailed(detailed) = spec
+                && (detailed.coordinates.is_none() || detailed.scope.is_some())
+            {
+                bail!("annotation processor '{name}' needs Maven `coordinates` and no `scope`");
+            }
+            if default_scope != Scope::Processor
+                && let DependencySpec::Detailed(detailed) = spec
+                && detailed.scope == Some(Scope::Processor)
+            {
+                bail!(
+                    "dependency '{name}' has scope \"processor\", declare it in [annotation-processors] instead"
+                );
+            }
+            let (coordinates, scope) = match spec {
+                DependencySpec::Simple(coordinates) => (coordinates, None),
+                DependencySpec::Detailed(detailed) => {
+                    let sources = [
+                        detailed.coordinates.is_some(),
+                        detailed.path.is_some(),
+                        detailed.git.is_some(),
+                    ];
+                    if sources.iter().filter(|s| **s).count() != 1 {
+                        bail!(
+                            "dependency '{name}' needs exactly one of `coordinates`, `path` or `git`"
+                        );
+                    }
+                    if detailed.rev.is_some() && detailed.git.is_none() {
+                        bail!("dependency '{name}' has a `rev` but no `git` repository");
+                    }
+                    match &detailed.coordinates {
+                        Some(coordinates) => (coordinates, detailed.scope),
+                        None => continue,
+                    }
+                }
+            };
+            let coordinate = coordinates
+                .parse()
+                .with_context(|| format!("invalid dependency '{name}'"))?;
+            declared.push(Declared {
+                name: name.clone(),
+                coordinate,
+                scope: scope.unwrap_or(default_scope),
+            });
+        }
+    }
+    Ok(declared)
+}
+
+/// Resolves the dependencies of the project, reusing the versions pinned in
+/// `Cup.lock` while they still match `Cup.toml` and updating it otherwise.
+pub fn resolve_dependencies(
+    config: &CupConfig,
+    options: &ResolveOptions,
+) -> Result<Vec<ResolvedArtifact>> {
+    let declared = declared_dependencies(config, options)?;
+    let mut requires: Vec<String> = declared
+        .iter()
+        .map(|d| format!("{}:{}", d.coordinate, d.scope))
+        .collect();
+    requires.sort();
+
+    let repositories = project_repositories(config, options)?;
+    let lock_path = lockfile_path();
+    let lockfile = Lockfile::load(&lock_path);
+
+    if let Some(lockfile) = &lockfile
+        && let Some(locked) = lockfile.project(&config.project.name)
+        && locked.requires == requires
+    {
+        return install_locked(lockfile, locked, &repositories, options.offline);
+    }
+    if options.locked {
+        bail!(
+            "{LOCKFILE} needs to be updated but --locked was passed; \
+             run the command without --locked to update it"
+        );
+    }
+
+    let resolved = Resolver::new(repositories, options.offline)?.resolve(&declared)?;
+
+    let mut lockfile = lockfile.unwrap_or_default();
+    lockfile.update(
+        LockedProject {
+            name: config.project.name.clone(),
+            requires,
+            resolved: resolved
+                .iter()
+                .map(|a| format!("{}:{}", a.coordinate, a.scope))
+                .collect(),
+        },
+        resolved
+            .iter()
+            .map(|a| LockedPackage {
+                id: a.coordinate.to_string(),
+                repository: a.repository.clone(),
+                checksum: a.checksum.clone(),
+            })
+            .collect(),
+    );
+    lockfile.save(&lock_path)?;
+
+    Ok(resolved)
+}
+
+/// Repositories of the project, followed by the ones its path and git dependencies use.
+pub fn project_repositories(
+    config: &CupConfig,
+    options: &ResolveOptions,
+) -> Result<Vec<Repository>> {
+    let mut repositories = Repository::from_config(config, options.offline)?;
+    for project in local::local_projects(config, Path::new("."), DEFAULT_PROFILE, options)? {
+        for repository in Repository::from_config(&project.config, options.offline)? {
+            if !repositories
+                .iter()
+                .any(|r| r.to_string() == repository.to_string())
+            {
+                repositories.push(repository);
+            }
+        }
+    }
+    Ok(repositories)
+}
+
+/// Fetches exactly the artifacts recorded in the lockfile and checks their checksums.
+fn install_locked(
+    lockfile: &Lockfile,
+    project: &LockedProject,
+    repositories: &[Repository],
+    offline: bool,
+) -> Result<Vec<ResolvedArtifact>> {
+    let cache = repository::cache_dir()?;
+    let mut resolved = vec![];
+
+    for entry in &project.resolved {
+        let (id, scope) = entry
+            .rsplit_once(':')
+            .with_context(|| format!("{LOCKFILE} is corrupt: bad entry '{entry}'"))?;
+        let package = lockfile
+            .package(id)
+            .with_context(|| format!("{LOCKFILE} is corrupt: no [[package]] entry for {id}"))?;
+        let coordinate: Coordinate = id.parse()?;
+
+        let mut sources = vec![];
+        if package.repository != "cache" {
+            sources.push(Repository::from_url(&package.repository));
+        }
+        sources.extend(repositories.iter().cloned());
+        if offline {
+            sources.retain(|r| matches!(r, Repository::Local(_)));
+        }
+        let path = fetch_artifact(&coordinate, "jar", &sources, &cache)
+            .map_err(|e| offline_hint(e, offline))?;
+
+        let checksum = sha256_file(&path)?;
+        if checksum != package.checksum {
+            bail!(
+                "checksum mismatch for {id}:\n  {LOCKFILE}: {}\n  {}: {checksum}",
+                package.checksum,
+                path.display()
+            );
+        }
+
+        resolved.push(ResolvedArtifact {
+            coordinate,
+            scope: scope.parse()?,
+            path,
+            repository: package.repository.clone(),
+            checksum,
+        });
+    }
+
+    Ok(resolved)
+}
+
+/// Walks POM files breadth-first, so that the version nearest to the project
+/// wins when the same `group:artifact` is reached more than once.
+pub struct Resolver {
+    repositories: Vec<Repository>,
+    cache: PathBuf,
+    poms: HashMap<Coordinate, Option<Pom>>,
+    edges: Vec<Edge>,
+    offline: bool,
+    /// Versions to use instead of mediating, see `pin`
+    pins: Vec<ResolvedArtifact>,
+}
+
+/// One "depends on" relation seen while resolving, kept for `cup tree`.
+#[derive(Debug, Clone)]
+pub struct Edge {
+    /// `None` for dependencies declared in `Cup.toml`
+    pub parent: Option<Coordinate>,
+    pub requested: Coordinate,
+    pub scope: Scope,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// This edge brought the artifact into the graph
+    Selected,
+    /// The same version was already reached through another path
+    Duplicate,
+    /// Another version won mediation
+    Conflict(String),
+}
+
+/// The version picked for a `group:artifact` and the widest scope it was reached with.
+struct Selection {
+    version: String,
+    scope: Scope,
+    /// Declared in Cup.toml, which fixes its scope as written
+    direct: bool,
+}
+
+struct Pending {
+    coordinate: Coordinate,
+    scope: Scope,
+    exclusions: Vec<Exclusion>,
+    jar: bool,
+    /// Already resolved, walked again only to widen the scope of its dependencies
+    revisit: bool,
+}
+
+impl Resolver {
+    pub fn new(repositories: Vec<Repository>, offline: bool) -> Result<Self> {
+        Ok(Self {
+            repositories,
+            cache: repository::cache_dir()?,
+            poms: HashMap::new(),
+            edges: vec![],
+            offline,
+            pins: vec![],
+        })
+    }
+
+    /// Makes the walk use the versions of `artifacts`, e.g. the ones in `Cup.lock`,
+    /// so the edges show what the build actually uses.
+    pub fn pin(&mut self, artifacts: &[ResolvedArtifact]) {
+        self.pins = artifacts.to_vec();
+    }
+
+    /// Every dependency relation seen by the last call to `resolve`.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Resolves the roots in a single breadth-first walk: the version nearest to the project
+    /// wins, and an artifact reached through several paths gets the widest of their scopes.
+    pub fn resolve(&mut self, roots: &[Declared]) -> Result<Vec<ResolvedArtifact>> {
+        self.edges.clear();
+        // Annotation processors run inside javac, apart from the project's classpath,
+        // so their versions are picked in a graph of their own
+        let (processors, project): (Vec<Declared>, Vec<Declared>) = roots
+            .iter()
+            .cloned()
+            .partition(|root| root.scope == Scope::Processor);
+        let mut resolved = self.resolve_graph(&project)?;
+        resolved.extend(self.resolve_graph(&processors)?);
+        Ok(resolved)
+    }
+
+    fn resolve_graph(&mut self, roots: &[Declared]) -> Result<Vec<ResolvedArtifact>> {
+        let mut selected: HashMap<(String, String), Selection> = HashMap::new();
+        let mut resolved = vec![];
+        let processors = roots.iter().any(|r| r.scope == Scope::Processor);
+        let pins: HashMap<(String, String), String> = self
+            .pins
+            .iter()
+            .filter(|a| (a.scope == Scope::Processor) == processors)
+            .map(|a| {
+                let c = &a.coordinate;
+                ((c.group.clone(), c.artifact.clone()), c.version.clone())
+            })
+            .collect();
+
+        // Direct dependencies are the nearest of all, whatever their scope
+        let mut queue = VecDeque::new();
+        for root in roots {
+            let coordinate = &root.coordinate;
+            let key = (coordinate.group.clone(), coordinate.artifact.clone());
+            let outcome = selected.get(&key).map(|winner| {
+                eprintln!(
+                    "{} dependency '{}' ({coordinate}) is also declared with version {}, ignoring it",
+                    style("warning:").yellow().bold(),
+                    root.name,
+                    winner.version
+                );
+                Outcome::Conflict(winner.version.clone())
+            });
+            self.edges.push(Edge {
+                parent: None,
+                requested: coordinate.clone(),
+                scope: root.scope,
+                outcome: outcome.clone().unwrap_or(Outcome::Selected),
+            });
+            if outcome.is_none() {
+                selected.insert(
+                    key,
+                    Selection {
+                        version: coordinate.version.clone(),
+                        scope: root.scope,
+                        direct: true,
+                    },
+                );
+                queue.push_back(Pending {
+                    coordinate: coordinate.clone(),
+                    scope: root.scope,
+                    exclusions: vec![],
+                    jar: true,
+                    revisit: false,
+                });
+            }
+        }
+
+        self.walk(queue, &pins, &mut selected, &mut resolved)?;
+        Ok(resolved)
+    }
+
+    fn walk(
+        &mut self,
+        mut queue: VecDeque<Pending>,
+        pins: &HashMap<(String, String), String>,
+        selected: &mut HashMap<(String, String), Selection>,
+        resolved: &mut Vec<ResolvedArtifact>,
+    ) -> Result<()> {
+        while let Some(node) = queue.pop_front() {
+            // Another path may have widened the scope since this node was queued
+            let key = (
+                node.coordinate.group.clone(),
+                node.coordinate.artifact.clone(),
+            );
+            let node_scope = selected.get(&key).map_or(node.scope, |s| s.scope);
+            let pom = self.effective_pom(&node.coordinate)?;
+            if !node.revisit {
+                if pom.is_none() && self.offline {
+                    // Offline, a missing POM most likely just was never downloaded
+                    return Err(offline_hint(
+                        anyhow::anyhow!("the POM for {} is not available locally", node.coordinate),
+                        true,
+                    ));
+                }
+                if pom.is_none() {
+                    eprintln!(
+                        "{} the POM for {} is missing, no dependency information available",
+                        style("warning:").yellow().bold(),
+                        node.coordinate
+                    );
+                }
+
+                let packaging = pom.as_ref().and_then(|p| p.packaging.as_deref());
+                if node.jar && packaging != Some("pom") {
+                    let path =
+                        fetch_artifact(&node.coordinate, "jar", &self.repositories, &self.cache)
+                            .map_err(|e| offline_hint(e, self.offline))?;
+                    resolved.push(ResolvedArtifact {
+                        repository: repository::origin_of(&path),
+                        checksum: sha256_file(&path)?,
+                        coordinate: node.coordinate.clone(),
+                        scope: node_scope,
+                        path,
+                    });
+                }
+            }
+
+            let Some(pom) = pom else { continue };
+            for dependency in &pom.dependencies {
+                let Some(scope) = node_scope.transitive(dependency.scope.as_deref()) else {
+                    continue;
+                };
+                if dependency.optional || dependency.classifier.is_some() {
+                    continue;
+                }
+                if node
+                    .exclusions
+                    .iter()
+                    .any(|e| e.matches(&dependency.group, &dependency.artifact))
+                {
+                    continue;
+                }
+                let Some(version) = &dependency.version else {
+                    bail!(
+                        "{}:{} (required by {}) has no version",
+                        dependency.group,
+                        dependency.artifact,
+                        node.coordinate
+                    );
+                };
+
+                let mut version = pick_version(version);
+                // The nearest path gets the pinned version, later ones still show their conflicts
+                if !selected.contains_key(&dependency.key())
+                    && let Some(pinned) = pins.get(&dependency.key())
+                {
+                    version = pinned.clone();
+                }
+                let requested = Coordinate {
+                    group: dependency.group.clone(),
+                    artifact: dependency.artifact.clone(),
+                    version: version.clone(),
+                };
+                let key = dependency.key();
+                let mut exclusions = node.exclusions.clone();
+                exclusions.extend(dependency.exclusions.iter().cloned());
+
+                let outcome = match selected.get_mut(&key) {
+                    Some(winner) => {
+                        let outcome = if winner.version == version {
+                            Outcome::Duplicate
+                        } else {
+                            Outcome::Conflict(winner.version.clone())
+                        };
+                        // The nearest version stays, but it is needed wherever this path needs it
+                        let widened = winner.scope.widen(scope);
+                        if !winner.direct && widened != winner.scope {
+                            winner.scope = widened;
+                            let coordinate = Coordinate {
+                                version: winner.version.clone(),
+                                ..requested.clone()
+                            };
+                            for artifact in resolved.iter_mut() {
+                                if artifact.coordinate == coordinate {
+                                    artifact.scope = widened;
+                                }
+                            }
+                            queue.push_back(Pending {
+                                coordinate,
+                                scope: widened,
+                                exclusions: exclusions.clone(),
+                                jar: false,
+                                revisit: true,
+                            });
+                        }
+                        outcome
+                    }
+                    None => Outcome::Selected,
+                };
+                if !node.revisit {
+                    self.edges.push(Edge {
+                        parent: Some(node.coordinate.clone()),
+                        requested: requested.clone(),
+                        scope,
+                        outcome: outcome.clone(),
+                    });
+                }
+                if outcome != Outcome::Selected {
+                    continue;
+                }
+                selected.insert(
+                    key,
+                    Selection {
+                        version,
+                        scope,
+                        direct: false,
+                    },
+                );
+
+                queue.push_back(Pending {
+                    coordinate: requested,
+                    scope,
+                    exclusions,
+                    jar: !dependency.is_pom(),
+                    revisit: false,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads a POM with its parents, imported BOMs and properties applied.
+    /// Returns `None` when no repository has a POM for the artifact.
+    fn effective_pom(&mut self, coordinate: &Coordinate) -> Result<Option<Pom>> {
+        self.effective_pom_at_depth(coordinate, 0)
+    }
+
+    fn effective_pom_at_depth(
+        &mut self,
+        coordinate: &Coordinate,
+        depth: usize,
+    ) -> Result<Option<Pom>> {
+        if let Some(pom) = self.poms.get(coordinate) {
+            return Ok(pom.clone());
+        }
+        if depth > 16 {
+            bail!("POM inheritance for {coordinate} is too deep or circular");
+        }
+
+        let Some(path) = fetch_optional(coordinate, "pom", &self.repositories, &self.cache)? else {
+            self.poms.insert(coordinate.clone(), None);
+            return Ok(None);
+        };
+        let xml = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut pom =
+            Pom::parse(&xml).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        if let Some(parent) = pom.parent.clone() {
+            match self.effective_pom_at_depth(&parent, depth + 1)? {
+                Some(parent_pom) => pom.inherit(&parent_pom),
+                None => bail!("parent POM {parent} of {coordinate} could not be found"),
+            }
+        }
+        pom.interpolate();
+
+        let imports: Vec<Coordinate> = pom
+            .dependency_management
+            .iter()
+            .filter(|m| m.scope.as_deref() == Some("import") && m.is_pom())
+            .filter_map(|m| {
+                Some(Coordinate {
+                    group: m.group.clone(),
+                    artifact: m.artifact.clone(),
+                    version: pick_version(m.version.as_deref()?),
+                })
+            })
+            .collect();
+        let mut seen: HashSet<(String, String)> =
+            pom.dependency_management.iter().map(|m| m.key()).collect();
+        for bom in imports {
+            let Some(bom_pom) = self.effective_pom_at_depth(&bom, depth + 1)? else {
+                bail!("imported BOM {bom} of {coordinate} could not be found");
+            };
+            for managed in bom_pom.dependency_management {
+                if seen.insert(managed.key()) {
+                    pom.dependency_management.push(managed);
+                }
+            }
+        }
+        pom.apply_management();
+
+        self.poms.insert(coordinate.clone(), Some(pom.clone()));
+        Ok(Some(pom))
+    }
+}
+
+fn offline_hint(err: anyhow::Error, offline: bool) -> anyhow::Error {
+    if !offline {
+        return err;
+    }
+    anyhow::anyhow!(
+        "{err}\nCup is offline (--offline or CUP_OFFLINE), so only the local cache \
+         and ~/.m2/repository were searched. Run once with network access to populate the cache."
+    )
+}
+
+/// Returns the cached file for an artifact, fetching it from the first
+/// repository that has it when it is not cached yet.
+pub fn fetch_artifact(
+    coordinate: &Coordinate,
+    extension: &str,
+    repositories: &[Repository],
+    cache: &Path,
+) -> Result<PathBuf> {
+    match fetch_optional(coordinate, extension, repositories, cache)? {
+        Some(path) => Ok(path),
+        None => bail!(
+            "{} not found in any repository ({})",
+            coordinate.repository_path(extension),
+            repositories
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn fetch_optional(
+    coordinate: &Coordinate,
+    extension: &str,
+    repositories: &[Repository],
+    cache: &Path,
+) -> Result<Option<PathBuf>> {
+    let relative = coordinate.repository_path(extension);
+    let cached = cache.join(&relative);
+    if cached.exists() {
+        return Ok(Some(cached));
+    }
+
+    for repository in repositories {
+        if repository.fetch(&relative, &cached)? {
+            repository::record_origin(&cached, repository)?;
+            report::status(format_args!(
+                "{} {} from {}",
+                style("Downloaded").green().bold(),
+                relative.rsplit('/').next().unwrap_or(&relative),
+                repository
+            ));
+            return Ok(Some(cached));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Maven-layout directory with a POM and an empty jar per artifact, and an empty cache.
+    struct TestRepository {
+        root: PathBuf,
+    }
+
+    impl TestRepository {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("cup-deps-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        /// `dependencies` are `(group:artifact:version, scope)`.
+        fn publish(&self, coordinates: &str, dependencies: &[(&str, Option<&str>)]) {
+            let coordinate: Coordinate = coordinates.parse().unwrap();
+            let dependencies: String = dependencies
+                .iter()
+                .map(|(dependency, scope)| {
+                    let dependency: Coordinate = dependency.parse().unwrap();
+                    let scope = scope
+                        .map(|scope| format!("<scope>{scope}</scope>"))
+                        .unwrap_or_default();
+                    format!(
+                        "<dependency><groupId>{}</groupId><artifactId>{}</artifactId>\
+                         <version>{}</version>{scope}</dependency>",
+                        dependency.group, dependency.artifact, dependency.version
+                    )
+                })
+                .collect();
+            let pom = format!(
+                "<project><groupId>{}</groupId><artifactId>{}</artifactId><version>{}</version>\
+                 <dependencies>{dependencies}</dependencies></project>",
+                coordinate.group, coordinate.artifact, coordinate.version
+            );
+            let repo = self.root.join("repo");
+            let pom_path = repo.join(coordinate.repository_path("pom"));
+            fs::create_dir_all(pom_path.parent().unwrap()).unwrap();
+            fs::write(&pom_path, pom).unwrap();
+            fs::write(repo.join(coordinate.repository_path("jar")), b"").unwrap();
+        }
+
+        fn resolve(&self, roots: &[(&str, Scope)]) -> Vec<String> {
+            let mut resolver = Resolver {
+                repositories: vec![Repository::Local(self.root.join("repo"))],
+                cache: self.root.join("cache"),
+                poms: HashMap::new(),
+                edges: vec![],
+                offline: false,
+                pins: vec![],
+            };
+            let roots: Vec<Declared> = roots
+                .iter()
+                .map(|(coordinates, scope)| Declared {
+                    name: coordinates.to_string(),
+                    coordinate: coordinates.parse().unwrap(),
+                    scope: *scope,
+                })
+                .collect();
+            let mut resolved: Vec<String> = resolver
+                .resolve(&roots)
+                .unwrap()
+                .iter()
+                .map(|a| format!("{}:{}", a.coordinate, a.scope))
+                .collect();
+            resolved.sort();
+            resolved
+        }
+    }
+
+    impl Drop for TestRepository {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn transitive_scopes_follow_maven() {
+        assert_eq!(Scope::Compile.transitive(None), Some(Scope::Compile));
+        assert_eq!(
+            Scope::Compile.transitive(Some("runtime")),
+            Some(Scope::Runtime)
+        );
+        assert_eq!(Scope::Runtime.transitive(None), Some(Scope::Runtime));
+        assert_eq!(Scope::Provided.transitive(None), Some(Scope::Provided));
+        assert_eq!(Scope::Test.transitive(Some("runtime")), Some(Scope::Test));
+        assert_eq!(Scope::Compile.transitive(Some("test")), None);
+        assert_eq!(Scope::Compile.transitive(Some("provided")), None);
+    }
+
+    #[test]
+    fn widening_prefers_compile_then_runtime() {
+        assert_eq!(Scope::Provided.widen(Scope::Compile), Scope::Compile);
+        assert_eq!(Scope::Provided.widen(Scope::Runtime), Scope::Runtime);
+        assert_eq!(Scope::Runtime.widen(Scope::Provided), Scope::Runtime);
+        assert_eq!(Scope::Test.widen(Scope::Provided), Scope::Provided);
+        assert_eq!(Scope::Test.widen(Scope::Test), Scope::Test);
+    }
+
+    #[test]
+    fn shared_dependency_gets_the_wider_scope() {
+        let repo = TestRepository::new("widen");
+        repo.publish("org.a:provided:1", &[("org.a:shared:1", None)]);
+        repo.publish("org.a:runtime:1", &[("org.a:shared:1", None)]);
+        repo.publish("org.a:shared:1", &[("org.a:leaf:1", None)]);
+        repo.publish("org.a:leaf:1", &[]);
+
+        let resolved = repo.resolve(&[
+            ("org.a:provided:1", Scope::Provided),
+            ("org.a:runtime:1", Scope::Runtime),
+        ]);
+        assert_eq!(
+            resolved,
+            [
+                "org.a:leaf:1:runtime",
+                "org.a:provided:1:provided",
+                "org.a:runtime:1:runtime",
+                "org.a:shared:1:runtime",
+            ]
+        );
+    }
+
+    #[test]
+    fn nearest_version_wins_across_scopes() {
+        let repo = TestRepository::new("nearest");
+        repo.publish("org.a:app:1", &[("org.a:middle:1", None)]);
+        repo.publish("org.a:middle:1", &[("org.a:shared:1", None)]);
+        repo.publish("org.a:testlib:1", &[("org.a:shared:2", None)]);
+        repo.publish("org.a:shared:1", &[]);
+        repo.publish("org.a:shared:2", &[]);
+
+        let resolved = repo.resolve(&[
+            ("org.a:app:1", Scope::Compile),
+            ("org.a:testlib:1", Scope::Test),
+        ]);
+        assert_eq!(
+            resolved,
+            [
+                "org.a:app:1:compile",
+                "org.a:middle:1:compile",
+                "org.a:shared:2:compile",
+                "org.a:testlib:1:test",
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_scope_is_kept() {
+        let repo = TestRepository::new("direct");
+        repo.publish("org.a:app:1", &[("org.a:junit:1", None)]);
+        repo.publish("org.a:junit:1", &[]);
+
+        let resolved = repo.resolve(&[
+            ("org.a:junit:1", Scope::Test),
+            ("org.a:app:1", Scope::Compile),
+        ]);
+        assert_eq!(resolved, ["org.a:app:1:compile", "org.a:junit:1:test"]);
+    }
+}
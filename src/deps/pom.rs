@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use roxmltree::{Document, Node};
+
+use super::Coordinate;
+
+/// The parts of a `pom.xml` that matter for dependency resolution.
+#[derive(Debug, Clone, Default)]
+pub struct Pom {
+    pub parent: Option<Coordinate>,
+    pub group: Option<String>,
+    pub artifact: String,
+    pub version: Option<String>,
+    pub packaging: Option<String>,
+    pub properties: HashMap<String, String>,
+    pub dependency_management: Vec<PomDependency>,
+    pub dependencies: Vec<PomDependency>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PomDependency {
+    pub group: String,
+    pub artifact: String,
+    pub version: Option<String>,
+    pub scope: Option<String>,
+    pub kind: Option<String>,
+    pub classifier: Option<String>,
+    pub optional: bool,
+    pub exclusions: Vec<Exclusion>,
+}
+
+impl PomDependency {
+    pub fn key(&self) -> (String, String) {
+        (self.group.clone(), self.artifact.clone())
+    }
+
+    pub fn is_pom(&self) -> bool {
+        self.kind.as_deref() == Some("pom")
+    }
+}
+
+/// A `group:artifact` pair to leave out of the graph; either part may be `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exclusion {
+    pub group: String,
+    pub artifact: String,
+}
+
+impl Exclusion {
+    pub fn matches(&self, group: &str, artifact: &str) -> bool {
+        (self.group == "*" || self.group == group)
+            && (self.artifact == "*" || self.artifact == artifact)
+    }
+}
+
+impl Pom {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let doc = Document::parse(xml).context("malformed POM")?;
+        let project = doc.root_element();
+
+        let parent = child(project, "parent").and_then(|p| {
+            Some(Coordinate {
+                group: text(p, "groupId")?,
+                artifact: text(p, "artifactId")?,
+                version: text(p, "version")?,
+            })
+        });
+
+        let properties = child(project, "properties")
+            .map(|props| {
+                props
+                    .children()
+                    .filter(Node::is_element)
+                    .map(|p| {
+                        (
+                            p.tag_name().name().to_string(),
+                            p.text().unwrap_or_default().trim().to_string(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let dependency_management = child(project, "dependencyManagement")
+            .and_then(|m| child(m, "dependencies"))
+            .map(parse_dependencies)
+            .unwrap_or_default();
+
+        Ok(Self {
+            parent,
+            group: text(project, "groupId"),
+            artifact: text(project, "artifactId").unwrap_or_default(),
+            version: text(project, "version"),
+            packaging: text(project, "packaging"),
+            properties,
+            dependency_management,
+            dependencies: child(project, "dependencies")
+                .map(parse_dependencies)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Folds an already-effective parent into this POM: inherited coordinates,
+    /// properties, managed versions and dependencies, with this POM taking precedence.
+    pub fn inherit(&mut self, parent: &Pom) {
+        if self.group.is_none() {
+            self.group = parent.group.clone();
+        }
+        if self.version.is_none() {
+            self.version = parent.version.clone();
+        }
+        for (key, value) in &parent.properties {
+            self.properties
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for managed in &parent.dependency_management {
+            if !self
+                .dependency_management
+                .iter()
+                .any(|d| d.key() == managed.key())
+            {
+                self.dependency_management.push(managed.clone());
+            }
+        }
+        for dependency in &parent.dependencies {
+            if !self
+                .dependencies
+                .iter()
+                .any(|d| d.key() == dependency.key())
+            {
+                self.dependencies.push(dependency.clone());
+            }
+        }
+        self.properties.insert(
+            "project.parent.groupId".to_string(),
+            parent.group.clone().unwrap_or_default(),
+        );
+        self.properties.insert(
+            "project.parent.version".to_string(),
+            parent.version.clone().unwrap_or_default(),
+        );
+    }
+
+    /// Substitutes `${...}` references in every dependency of this POM.
+    pub fn interpolate(&mut self) {
+        let mut properties = self.properties.clone();
+        let group = self.group.clone().unwrap_or_default();
+        let version = self.version.clone().unwrap_or_default();
+        for (key, value) in [
+            ("project.groupId", &group),
+            ("pom.groupId", &group),
+            ("groupId", &group),
+            ("project.artifactId", &self.artifact),
+            ("pom.artifactId", &self.artifact),
+            ("artifactId", &self.artifact),
+            ("project.version", &version),
+            ("pom.version", &version),
+            ("version", &version),
+        ] {
+            properties.insert(key.to_string(), value.clone());
+        }
+        if let Some(parent_version) = properties.get("project.parent.version").cloned() {
+            properties.insert("parent.version".to_string(), parent_version);
+        }
+
+        for dependency in self
+            .dependencies
+            .iter_mut()
+            .chain(self.dependency_management.iter_mut())
+        {
+            dependency.group = interpolate(&dependency.group, &properties);
+            dependency.artifact = interpolate(&dependency.artifact, &properties);
+            dependency.version = dependency
+                .version
+                .as_deref()
+                .map(|v| interpolate(v, &properties));
+            dependency.scope = dependency
+                .scope
+                .as_deref()
+                .map(|s| interpolate(s, &properties));
+        }
+    }
+
+    /// Fills in missing versions, scopes and exclusions from `<dependencyManagement>`.
+    pub fn apply_management(&mut self) {
+        for dependency in &mut self.dependencies {
+            let Some(managed) = self
+                .dependency_management
+                .iter()
+                .find(|m| m.key() == dependency.key())
+            else {
+                continue;
+            };
+            if dependency.version.is_none() {
+                dependency.version = managed.version.clone();
+            }
+            if dependency.scope.is_none() {
+                dependency.scope = managed.scope.clone();
+            }
+            if dependency.exclusions.is_empty() {
+                dependency.exclusions = managed.exclusions.clone();
+            }
+        }
+    }
+}
+
+/// Resolves `${name}` references against `properties`, leaving unknown ones untouched.
+pub fn interpolate(value: &str, properties: &HashMap<String, String>) -> String {
+    let mut result = value.to_string();
+    // Properties may refer to other properties, so expand a few levels deep.
+    for _ in 0..8 {
+        let mut expanded = String::with_capacity(result.len());
+        let mut rest = result.as_str();
+        let mut changed = false;
+        while let Some(start) = rest.find("${") {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            let name = &rest[start + 2..start + end];
+            expanded.push_str(&rest[..start]);
+            match properties.get(name) {
+                Some(replacement) => {
+                    expanded.push_str(replacement);
+                    changed = true;
+                }
+                None => expanded.push_str(&rest[start..start + end + 1]),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        result = expanded;
+        if !changed {
+            break;
+        }
+    }
+    result
+}
+
+/// Picks a concrete version out of a Maven version range such as `[1.2,2.0)`.
+pub fn pick_version(version: &str) -> String {
+    let version = version.trim();
+    if !(version.starts_with('[') || version.starts_with('(')) {
+        return version.to_string();
+    }
+    let inner = version.trim_matches(|c| "[]()".contains(c));
+    let mut bounds = inner.split(',').map(str::trim);
+    let lower = bounds.next().unwrap_or_default();
+    let upper = bounds.next();
+    match upper {
+        Some(upper) if lower.is_empty() && version.ends_with(']') => upper.to_string(),
+        _ => lower.to_string(),
+    }
+}
+
+fn parse_dependencies(node: Node) -> Vec<PomDependency> {
+    node.children()
+        .filter(|n| n.has_tag_name("dependency"))
+        .map(|d| PomDependency {
+            group: text(d, "groupId").unwrap_or_default(),
+            artifact: text(d, "artifactId").unwrap_or_default(),
+            version: text(d, "version"),
+            scope: text(d, "scope"),
+            kind: text(d, "type"),
+            classifier: text(d, "classifier"),
+            optional: text(d, "optional").is_some_and(|o| o == "true"),
+            exclusions: child(d, "exclusions")
+                .map(|e| {
+                    e.children()
+                        .filter(|n| n.has_tag_name("exclusion"))
+                        .map(|x| Exclusion {
+                            group: text(x, "groupId").unwrap_or_else(|| "*".to_string()),
+                            artifact: text(x, "artifactId").unwrap_or_else(|| "*".to_string()),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
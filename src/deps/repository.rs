@@ -0,0 +1,145 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::commands::{CupConfig, cup_home};
+
+pub const MAVEN_CENTRAL: &str = "https://repo.maven.apache.org/maven2";
+
+/// A Maven-layout repository, either a directory on disk or an HTTP(S) mirror.
+#[derive(Debug, Clone)]
+pub enum Repository {
+    Local(PathBuf),
+    Remote(String),
+}
+
+impl Repository {
+    /// Builds the repository list from `[[repositories]]`, falling back to Maven Central.
+    /// Offline, only local directories are used, plus `~/.m2/repository` if it exists.
+    pub fn from_config(config: &CupConfig, offline: bool) -> Result<Vec<Self>> {
+        let mut repositories = match &config.repositories {
+            Some(repositories) if !repositories.is_empty() => repositories
+                .iter()
+                .map(|r| Self::from_url(&r.url))
+                .collect(),
+            _ => vec![Self::Remote(MAVEN_CENTRAL.to_string())],
+        };
+
+        if offline {
+            repositories.retain(|r| matches!(r, Self::Local(_)));
+            let m2 = expand_home("~/.m2/repository");
+            if m2.is_dir() {
+                repositories.push(Self::Local(m2));
+            }
+        }
+        Ok(repositories)
+    }
+
+    pub fn from_url(url: &str) -> Self {
+        let url = url.trim_end_matches('/');
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Self::Remote(url.to_string())
+        } else {
+            let path = url.strip_prefix("file://").unwrap_or(url);
+            Self::Local(expand_home(path))
+        }
+    }
+
+    /// Copies `relative` from this repository to `dest`.
+    /// Returns `Ok(false)` when the repository does not have the file.
+    pub fn fetch(&self, relative: &str, dest: &Path) -> Result<bool> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let partial = dest.with_extension("part");
+
+        match self {
+            Self::Local(root) => {
+                let source = root.join(relative);
+                if !source.is_file() {
+                    return Ok(false);
+                }
+                fs::copy(&source, &partial)
+                    .with_context(|| format!("Failed to copy {}", source.display()))?;
+            }
+            Self::Remote(base) => {
+                let url = format!("{base}/{relative}");
+                let spinner = ProgressBar::new_spinner();
+                spinner.set_style(
+                    ProgressStyle::with_template("{spinner} {wide_msg}")
+                        .unwrap()
+                        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ "),
+                );
+                spinner.set_message(format!("Downloading {url}"));
+                spinner.enable_steady_tick(Duration::from_millis(100));
+
+                let status = Command::new("curl")
+                    .args(["--fail", "--silent", "--show-error", "--location"])
+                    .arg("--output")
+                    .arg(&partial)
+                    .arg(&url)
+                    .status()
+                    .context("Failed to execute curl. Make sure curl is installed and in PATH.");
+                spinner.finish_and_clear();
+
+                if !status?.success() {
+                    let _ = fs::remove_file(&partial);
+                    return Ok(false);
+                }
+            }
+        }
+
+        fs::rename(&partial, dest)
+            .with_context(|| format!("Failed to write {}", dest.display()))?;
+        Ok(true)
+    }
+}
+
+impl fmt::Display for Repository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(path) => write!(f, "{}", path.display()),
+            Self::Remote(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Per-user artifact cache, laid out like a Maven repository.
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(cup_home()?.join("repository"))
+}
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        return Path::new(&home).join(rest);
+    }
+    PathBuf::from(path)
+}
+
+/// Remembers which repository a cached file came from, for `Cup.lock`.
+pub fn record_origin(cached: &Path, repository: &Repository) -> Result<()> {
+    fs::write(origin_file(cached), repository.to_string())
+        .with_context(|| format!("Failed to write {}", origin_file(cached).display()))
+}
+
+pub fn origin_of(cached: &Path) -> String {
+    fs::read_to_string(origin_file(cached))
+        .map(|origin| origin.trim().to_string())
+        .unwrap_or_else(|_| "cache".to_string())
+}
+
+fn origin_file(cached: &Path) -> PathBuf {
+    let mut name = cached.as_os_str().to_owned();
+    name.push(".origin");
+    PathBuf::from(name)
+}
@@ -0,0 +1,549 @@
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use dialoguer::console::style;
+use serde::Serialize;
+
+use crate::report::{self, Event};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        })
+    }
+}
+
+/// One message from javac or kotlinc.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    /// 1-based, from the compiler or from the position of its `^` marker
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+    /// javac's lint category, e.g. `unchecked`
+    pub code: Option<String>,
+    /// Lines the compiler printed after the marker, e.g. `symbol: method foo()`
+    pub notes: Vec<String>,
+    /// The source line as the compiler echoed it
+    #[serde(skip)]
+    source: Option<String>,
+    /// Width of the compiler's `^^^` marker
+    #[serde(skip)]
+    marker: usize,
+}
+
+/// Parses javac (`File.java:12: error: ...`) and kotlinc (`File.kt:12:5: error: ...`)
+/// output. Lines that belong to no diagnostic, like JVM warnings, become notes.
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    let mut blocks: Vec<(Diagnostic, Vec<&str>)> = vec![];
+    for line in output.lines() {
+        if line.trim().is_empty() || is_count(line) {
+            continue;
+        }
+        if let Some(diagnostic) = parse_header(line) {
+            blocks.push((diagnostic, vec![]));
+        } else if let Some((_, lines)) = blocks.last_mut() {
+            lines.push(line);
+        } else {
+            blocks.push((
+                diagnostic(None, None, None, Severity::Note, line.trim()),
+                vec![],
+            ));
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|(mut diagnostic, lines)| {
+            let Some(marker) = lines.iter().position(|line| is_marker(line)) else {
+                diagnostic.notes = lines.iter().map(|l| l.trim().to_string()).collect();
+                return diagnostic;
+            };
+            // message continuation, then the echoed source line, then the marker
+            if marker > 0 {
+                for continuation in &lines[..marker - 1] {
+                    diagnostic.message.push('\n');
+                    diagnostic.message.push_str(continuation);
+                }
+                diagnostic.source = Some(lines[marker - 1].to_string());
+            }
+            let indent = lines[marker].len() - lines[marker].trim_start().len();
+            let width = lines[marker].trim().chars().count();
+            diagnostic.column = diagnostic.column.or(Some(indent + 1));
+            diagnostic.marker = width;
+            diagnostic.notes = lines[marker + 1..]
+                .iter()
+                .map(|l| l.trim().to_string())
+                .collect();
+            diagnostic
+        })
+        .collect()
+}
+
+fn parse_header(line: &str) -> Option<Diagnostic> {
+    for (label, severity) in [
+        ("error: ", Severity::Error),
+        ("exception: ", Severity::Error),
+        ("warning: ", Severity::Warning),
+        ("note: ", Severity::Note),
+        ("Note: ", Severity::Note),
+        ("info: ", Severity::Note),
+    ] {
+        if let Some(message) = line.strip_prefix(label) {
+            return Some(diagnostic(None, None, None, severity, message));
+        }
+        let Some((location, message)) = line.split_once(&format!(": {label}")) else {
+            continue;
+        };
+        // path:line or path:line:column, where the path may contain `:` on Windows
+        let Some((rest, last)) = location.rsplit_once(':') else {
+            continue;
+        };
+        let Ok(last) = last.parse::<usize>() else {
+            continue;
+        };
+        let (file, line, column) = match rest.rsplit_once(':') {
+            Some((file, line)) if line.parse::<usize>().is_ok() => {
+                (file, line.parse().ok(), Some(last))
+            }
+            _ => (rest, Some(last), None),
+        };
+        return Some(diagnostic(
+            Some(PathBuf::from(file)),
+            line,
+            column,
+            severity,
+            message,
+        ));
+    }
+    None
+}
+
+fn diagnostic(
+    file: Option<PathBuf>,
+    line: Option<usize>,
+    column: Option<usize>,
+    severity: Severity,
+    message: &str,
+) -> Diagnostic {
+    // javac puts the lint category first: `[unchecked] unchecked call to ...`
+    let (code, message) = match message.strip_prefix('[').and_then(|m| m.split_once("] ")) {
+        Some((code, message)) if !code.contains(' ') => (Some(code.to_string()), message),
+        _ => (None, message),
+    };
+    Diagnostic {
+        file,
+        line,
+        column,
+        severity,
+        message: message.to_string(),
+        code,
+        notes: vec![],
+        source: None,
+        marker: 1,
+    }
+}
+
+/// javac's closing `2 errors` / `1 warning`.
+fn is_count(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    matches!(
+        (words.next(), words.next(), words.next()),
+        (Some(n), Some("error" | "errors" | "warning" | "warnings"), None)
+            if n.parse::<usize>().is_ok()
+    )
+}
+
+fn is_marker(line: &str) -> bool {
+    let marker = line.trim();
+    marker.starts_with('^') && marker.chars().all(|c| c == '^' || c == '~')
+}
+
+impl Diagnostic {
+    /// Colored like rustc: the message, its location and the source line with a marker.
+    pub fn render(&self) -> String {
+        let label = match self.severity {
+            Severity::Error => style(self.severity.to_string()).red().bold(),
+            Severity::Warning => style(self.severity.to_string()).yellow().bold(),
+            Severity::Note => style(self.severity.to_string()).cyan().bold(),
+        };
+        let code = self
+            .code
+            .as_ref()
+            .map(|code| format!("[{code}]"))
+            .unwrap_or_default();
+        let mut out = format!(
+            "{label}{}: {}\n",
+            style(code).bold(),
+            style(&self.message).bold()
+        );
+
+        let Some(file) = &self.file else {
+            for note in &self.notes {
+                out.push_str(&format!("  {note}\n"));
+            }
+            return out;
+        };
+        let gutter = self.line.map_or(1, |line| line.to_string().len());
+        let pad = " ".repeat(gutter);
+        let bar = style("|").blue().bold();
+        let mut location = file.display().to_string();
+        if let Some(line) = self.line {
+            location.push_str(&format!(":{line}"));
+        }
+        if let Some(column) = self.column {
+            location.push_str(&format!(":{column}"));
+        }
+        out.push_str(&format!("{pad}{} {location}\n", style("-->").blue().bold()));
+
+        let source = self.line.and_then(|line| {
+            fs::read_to_string(file)
+                .ok()
+                .and_then(|content| {
+                    content
+                        .lines()
+                        .nth(line.checked_sub(1)?)
+                        .map(str::to_string)
+                })
+                .or_else(|| self.source.clone())
+        });
+        if let (Some(line), Some(source)) = (self.line, source) {
+            out.push_str(&format!("{pad} {bar}\n"));
+            out.push_str(&format!(
+                "{} {bar} {source}\n",
+                style(line.to_string()).blue().bold()
+            ));
+            if let Some(column) = self.column {
+                // keep tabs so the marker lines up under them
+                let indent: String = source
+                    .chars()
+                    .take(column.saturating_sub(1))
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let marker = "^".repeat(self.marker.max(1));
+                let marker = match self.severity {
+                    Severity::Error => style(marker).red().bold(),
+                    Severity::Warning => style(marker).yellow().bold(),
+                    Severity::Note => style(marker).cyan().bold(),
+                };
+                out.push_str(&format!("{pad} {bar} {indent}{marker}\n"));
+            }
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{pad} {} {note}\n", style("=").blue().bold()));
+        }
+        out
+    }
+}
+
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Prints the diagnostics to stderr, or emits them as events with `--message-format json`,
+/// and adds them to the build's totals.
+pub fn report(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        if report::is_json() {
+            report::emit(Event::Diagnostic(diagnostic));
+        } else {
+            eprintln!("{}", diagnostic.render());
+        }
+    }
+    let counts = Summary::of(diagnostics);
+    ERRORS.fetch_add(counts.errors, Ordering::Relaxed);
+    WARNINGS.fetch_add(counts.warnings, Ordering::Relaxed);
+}
+
+/// Errors and warnings of a set of diagnostics, or of every compiler run so far.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Summary {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl Summary {
+    pub fn of(diagnostics: &[Diagnostic]) -> Self {
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|d| d.severity == severity)
+                .count()
+        };
+        Self {
+            errors: count(Severity::Error),
+            warnings: count(Severity::Warning),
+        }
+    }
+
+    pub fn total() -> Self {
+        Self {
+            errors: ERRORS.load(Ordering::Relaxed),
+            warnings: WARNINGS.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors == 0 && self.warnings == 0
+    }
+}
+
+/// `2 errors, 1 warning`
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize, what: &str| format!("{n} {what}{}", if n == 1 { "" } else { "s" });
+        let mut parts = vec![];
+        if self.errors > 0 {
+            parts.push(plural(self.errors, "error"));
+        }
+        if self.warnings > 0 {
+            parts.push(plural(self.warnings, "warning"));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `javac -Xlint:all` (JDK 17) on a file with raw types, a type error and a missing method.
+    const JAVAC: &str = "\
+src/Main.java:5: warning: [rawtypes] found raw type: List
+        List raw = new ArrayList();
+        ^
+  missing type arguments for generic class List<E>
+  where E is a type-variable:
+    E extends Object declared in interface List
+src/Main.java:6: warning: [unchecked] unchecked conversion
+        List<String> names = raw;
+                             ^
+  required: List<String>
+  found:    List
+src/Main.java:7: error: incompatible types: String cannot be converted to int
+        int x = \"text\";
+                ^
+src/Main.java:8: error: cannot find symbol
+        System.out.println(missing(x));
+                           ^
+  symbol:   method missing(int)
+  location: class Main
+2 errors
+2 warnings
+";
+
+    /// kotlinc with a JVM warning first and a message that continues before the source line.
+    const KOTLINC: &str = "\
+OpenJDK 64-Bit Server VM warning: Options -Xverify:none and -noverify were deprecated in JDK 13
+warning: language version 1.6 is deprecated and its support will be removed in a future version of Kotlin
+src/main/kotlin/Main.kt:2:9: warning: variable 'unused' is never used
+    val unused = 1
+        ^^^^^^
+src/main/kotlin/Main.kt:3:5: error: none of the following functions can be called with the arguments supplied: 
+public fun greet(name: String): Unit defined in root package in file Main.kt
+public fun greet(times: Int): Unit defined in root package in file Main.kt
+    greet(1.0)
+    ^^^^^
+";
+
+    #[test]
+    fn parses_javac_output() {
+        let diagnostics = parse(JAVAC);
+        assert_eq!(diagnostics.len(), 4);
+
+        let raw = &diagnostics[0];
+        assert_eq!(raw.file, Some(PathBuf::from("src/Main.java")));
+        assert_eq!((raw.line, raw.column), (Some(5), Some(9)));
+        assert_eq!(raw.severity, Severity::Warning);
+        assert_eq!(raw.code.as_deref(), Some("rawtypes"));
+        assert_eq!(raw.message, "found raw type: List");
+        assert_eq!(
+            raw.source.as_deref(),
+            Some("        List raw = new ArrayList();")
+        );
+        assert_eq!(
+            raw.notes,
+            [
+                "missing type arguments for generic class List<E>",
+                "where E is a type-variable:",
+                "E extends Object declared in interface List",
+            ]
+        );
+
+        let symbol = &diagnostics[3];
+        assert_eq!(symbol.severity, Severity::Error);
+        assert_eq!(symbol.code, None);
+        assert_eq!((symbol.line, symbol.column), (Some(8), Some(28)));
+        assert_eq!(
+            symbol.notes,
+            ["symbol:   method missing(int)", "location: class Main"]
+        );
+    }
+
+    #[test]
+    fn skips_javac_counts() {
+        let counts: Vec<Severity> = parse(JAVAC).iter().map(|d| d.severity).collect();
+        assert_eq!(
+            counts,
+            [
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Error,
+                Severity::Error
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_kotlinc_output() {
+        let diagnostics = parse(KOTLINC);
+        assert_eq!(diagnostics.len(), 4);
+
+        // lines that are not diagnostics are kept as notes without a location
+        let jvm = &diagnostics[0];
+        assert_eq!((jvm.severity, jvm.file.as_ref()), (Severity::Note, None));
+        assert!(jvm.message.starts_with("OpenJDK 64-Bit Server VM warning"));
+
+        let deprecated = &diagnostics[1];
+        assert_eq!(deprecated.severity, Severity::Warning);
+        assert_eq!(deprecated.file, None);
+
+        let unused = &diagnostics[2];
+        assert_eq!(unused.file, Some(PathBuf::from("src/main/kotlin/Main.kt")));
+        assert_eq!((unused.line, unused.column), (Some(2), Some(9)));
+        assert_eq!(unused.marker, 6);
+        assert!(unused.notes.is_empty());
+    }
+
+    #[test]
+    fn joins_continuation_lines_before_the_source_line() {
+        let overloads = &parse(KOTLINC)[3];
+        assert_eq!(overloads.severity, Severity::Error);
+        assert_eq!(
+            overloads.message,
+            "none of the following functions can be called with the arguments supplied: \n\
+             public fun greet(name: String): Unit defined in root package in file Main.kt\n\
+             public fun greet(times: Int): Unit defined in root package in file Main.kt"
+        );
+        assert_eq!(overloads.source.as_deref(), Some("    greet(1.0)"));
+        assert_eq!(overloads.marker, 5);
+    }
+
+    #[test]
+    fn keeps_continuation_lines_after_the_marker_as_notes() {
+        // an annotation processor's two-line message, as javac prints it
+        let output = "\
+src/Main.java:1: error: Unmapped target property: \"name\".
+public class Main {
+       ^
+  Mapping from Order to OrderDto.
+1 error
+";
+        let diagnostics = parse(output);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Unmapped target property: \"name\"."
+        );
+        assert_eq!(diagnostics[0].column, Some(8));
+        assert_eq!(diagnostics[0].notes, ["Mapping from Order to OrderDto."]);
+    }
+
+    #[test]
+    fn marker_column_counts_tabs_as_one_column() {
+        let output = "\
+src/Main.java:6: error: incompatible types: int cannot be converted to String
+\t\tnames.add(1);
+\t\t          ^
+";
+        let diagnostics = parse(output);
+        assert_eq!(diagnostics[0].column, Some(13));
+        assert!(diagnostics[0].render().contains("\t\t          ^"));
+    }
+
+    #[test]
+    fn parses_windows_paths() {
+        let java = parse_header(r"C:\Users\dev\app\src\Main.java:7: error: ';' expected").unwrap();
+        assert_eq!(
+            java.file,
+            Some(PathBuf::from(r"C:\Users\dev\app\src\Main.java"))
+        );
+        assert_eq!((java.line, java.column), (Some(7), None));
+        assert_eq!(java.message, "';' expected");
+
+        let kotlin = parse_header(
+            r"C:\Users\dev\app\src\Main.kt:3:5: warning: parameter 'args' is never used",
+        )
+        .unwrap();
+        assert_eq!(
+            kotlin.file,
+            Some(PathBuf::from(r"C:\Users\dev\app\src\Main.kt"))
+        );
+        assert_eq!((kotlin.line, kotlin.column), (Some(3), Some(5)));
+        assert_eq!(kotlin.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn reads_lint_codes() {
+        let lint = parse_header(
+            "src/A.java:3: warning: [deprecation] stop() in Thread has been deprecated",
+        )
+        .unwrap();
+        assert_eq!(lint.code.as_deref(), Some("deprecation"));
+        assert_eq!(lint.message, "stop() in Thread has been deprecated");
+
+        // brackets with spaces are part of the message
+        let message = parse_header("src/A.java:3: error: [not a code] here").unwrap();
+        assert_eq!(message.code, None);
+        assert_eq!(message.message, "[not a code] here");
+    }
+
+    #[test]
+    fn tries_the_next_label_when_a_message_contains_an_earlier_one() {
+        let warning = parse_header(
+            "src/Main.kt:4:9: warning: 'foo: error: bar' is a suspicious string literal",
+        )
+        .unwrap();
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.file, Some(PathBuf::from("src/Main.kt")));
+        assert_eq!((warning.line, warning.column), (Some(4), Some(9)));
+        assert_eq!(
+            warning.message,
+            "'foo: error: bar' is a suspicious string literal"
+        );
+    }
+
+    #[test]
+    fn rejects_lines_that_are_not_headers() {
+        assert!(parse_header("        List raw = new ArrayList();").is_none());
+        assert!(parse_header("  symbol:   method missing(int)").is_none());
+        assert!(parse_header("src/Main.java:x: error: oops").is_none());
+    }
+
+    #[test]
+    fn renders_a_zero_column() {
+        let output = "\
+src/Missing.kt:1:0: error: expecting a top level declaration
+}
+^
+";
+        let rendered = parse(output)[0].render();
+        assert!(rendered.contains("src/Missing.kt:1:0"));
+        assert!(rendered.contains("^"));
+    }
+}
@@ -0,0 +1,76 @@
+use std::{fmt, process::ExitStatus};
+
+/// An error that decides Cup's exit code. Other errors exit with [`OTHER`].
+#[derive(Debug)]
+pub enum Failure {
+    /// Cup.toml is missing or invalid
+    Config(String),
+    /// javac or kotlinc reported errors
+    Compile(String),
+    /// The JUnit launcher reported failed tests
+    Tests(String),
+    /// `cup run` could not start the program, or it was killed by a signal
+    Run(String),
+    /// A process Cup started exited with this status, which Cup exits with too
+    Exit(i32, String),
+}
+
+// Above the codes programs usually exit with, which `cup run` passes through
+const OTHER: u8 = 101;
+const CONFIG: u8 = 102;
+const COMPILE: u8 = 103;
+const TESTS: u8 = 104;
+const RUN: u8 = 105;
+
+impl Failure {
+    /// Marks an error from reading Cup.toml, keeping its causes in the message.
+    pub fn config(error: anyhow::Error) -> anyhow::Error {
+        if error.downcast_ref::<Failure>().is_some() {
+            return error;
+        }
+        Failure::Config(format!("{error:#}")).into()
+    }
+
+    /// The failure for a child process that did not succeed: its own exit code, or a
+    /// runtime failure if it has none because a signal killed it.
+    pub fn exited(status: ExitStatus, message: String) -> Self {
+        let message = format!("{message} ({status})");
+        match status.code() {
+            Some(code) => Failure::Exit(code, message),
+            None => Failure::Run(message),
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Config(_) => CONFIG,
+            Failure::Compile(_) => COMPILE,
+            Failure::Tests(_) => TESTS,
+            Failure::Run(_) => RUN,
+            // Exit codes are 0-255 on Unix; keep other platforms from reporting success
+            Failure::Exit(code, _) => u8::try_from(*code).unwrap_or(1).max(1),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Config(message)
+            | Failure::Compile(message)
+            | Failure::Tests(message)
+            | Failure::Run(message)
+            | Failure::Exit(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Cup's exit code for an error of any command.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<Failure>())
+        .map_or(OTHER, Failure::exit_code)
+}
@@ -0,0 +1,578 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+
+use super::sha256_hex;
+
+const ACC_PRIVATE: u16 = 0x0002;
+const ACC_STATIC: u16 = 0x0008;
+const ACC_FINAL: u16 = 0x0010;
+const ACC_SYNTHETIC: u16 = 0x1000;
+
+/// The parts of a `.class` file incremental compilation needs.
+#[derive(Debug)]
+pub struct ClassFile {
+    /// Internal name, e.g. `com/example/Main$Inner`
+    pub name: String,
+    /// `SourceFile` attribute, e.g. `Main.java`
+    pub source_file: Option<String>,
+    /// Every other class named in the constant pool or in a descriptor
+    pub references: BTreeSet<String>,
+    /// Hash of what other classes can see: supertypes and non-private member signatures
+    pub abi: String,
+    /// Hash of the `static final` constants, which javac copies into the classes using them
+    pub constants: String,
+}
+
+impl ClassFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&bytes).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.u4()? != 0xCAFEBABE {
+            bail!("not a class file");
+        }
+        reader.skip(4)?; // minor and major version
+
+        let pool = ConstantPool::read(&mut reader)?;
+
+        let access = reader.u2()?;
+        let name = pool.class_name(reader.u2()?)?.to_string();
+        let super_index = reader.u2()?;
+        let super_name = if super_index == 0 {
+            ""
+        } else {
+            pool.class_name(super_index)?
+        };
+        let mut interfaces = vec![];
+        for _ in 0..reader.u2()? {
+            interfaces.push(pool.class_name(reader.u2()?)?);
+        }
+
+        let mut abi = vec![format!(
+            "class {access:#x} {name} extends {super_name} implements {}",
+            interfaces.join(",")
+        )];
+        let mut members = vec![];
+        let mut constants = vec![];
+        for kind in ["field", "method"] {
+            for _ in 0..reader.u2()? {
+                let access = reader.u2()?;
+                let member = pool.utf8(reader.u2()?)?;
+                let descriptor = pool.utf8(reader.u2()?)?;
+                let attributes = read_attributes(&mut reader, &pool)?;
+                if access & (ACC_PRIVATE | ACC_SYNTHETIC) != 0 {
+                    continue;
+                }
+
+                let mut line = format!("{kind} {access:#x} {member} {descriptor}");
+                if let Some(signature) = attributes.signature {
+                    line.push_str(&format!(" signature {signature}"));
+                }
+                if !attributes.exceptions.is_empty() {
+                    line.push_str(&format!(" throws {}", attributes.exceptions.join(",")));
+                }
+                if let Some(value) = attributes.constant_value
+                    && access & (ACC_STATIC | ACC_FINAL) == ACC_STATIC | ACC_FINAL
+                {
+                    constants.push(format!("{member} = {value}"));
+                }
+                members.push(line);
+            }
+        }
+
+        let attributes = read_attributes(&mut reader, &pool)?;
+        if let Some(signature) = attributes.signature {
+            abi.push(format!("signature {signature}"));
+        }
+        // Member order in the class file follows the source, which is not part of the ABI
+        members.sort();
+        constants.sort();
+        abi.extend(members);
+
+        let mut references = pool.referenced_classes();
+        references.remove(&name);
+
+        Ok(Self {
+            name,
+            source_file: attributes.source_file,
+            references,
+            abi: sha256_hex(abi.join("\n").as_bytes()),
+            constants: sha256_hex(constants.join("\n").as_bytes()),
+        })
+    }
+
+    pub fn package(&self) -> &str {
+        self.name
+            .rsplit_once('/')
+            .map_or("", |(package, _)| package)
+    }
+}
+
+#[derive(Default)]
+struct Attributes {
+    source_file: Option<String>,
+    signature: Option<String>,
+    constant_value: Option<String>,
+    exceptions: Vec<String>,
+}
+
+fn read_attributes(reader: &mut Reader, pool: &ConstantPool) -> Result<Attributes> {
+    let mut attributes = Attributes::default();
+    for _ in 0..reader.u2()? {
+        let name = pool.utf8(reader.u2()?)?;
+        let length = reader.u4()? as usize;
+        let mut body = Reader {
+            bytes: reader.take(length)?,
+            pos: 0,
+        };
+        match name {
+            "SourceFile" => attributes.source_file = Some(pool.utf8(body.u2()?)?.to_string()),
+            "Signature" => attributes.signature = Some(pool.utf8(body.u2()?)?.to_string()),
+            "ConstantValue" => attributes.constant_value = Some(pool.value(body.u2()?)?),
+            "Exceptions" => {
+                for _ in 0..body.u2()? {
+                    attributes
+                        .exceptions
+                        .push(pool.class_name(body.u2()?)?.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(attributes)
+}
+
+enum Constant {
+    Utf8(String),
+    Integer(i32),
+    Float(u32),
+    Long(i64),
+    Double(u64),
+    Class(u16),
+    String(u16),
+    Other,
+}
+
+struct ConstantPool {
+    entries: Vec<Constant>,
+}
+
+impl ConstantPool {
+    fn read(reader: &mut Reader) -> Result<Self> {
+        let count = reader.u2()? as usize;
+        // index 0 is unused
+        let mut entries = vec![Constant::Other];
+        while entries.len() < count {
+            let tag = reader.u1()?;
+            let entry = match tag {
+                1 => {
+                    let length = reader.u2()? as usize;
+                    Constant::Utf8(String::from_utf8_lossy(reader.take(length)?).to_string())
+                }
+                3 => Constant::Integer(reader.u4()? as i32),
+                4 => Constant::Float(reader.u4()?),
+                5 => Constant::Long(reader.u8()? as i64),
+                6 => Constant::Double(reader.u8()?),
+                7 => Constant::Class(reader.u2()?),
+                8 => Constant::String(reader.u2()?),
+                16 | 19 | 20 => {
+                    reader.skip(2)?;
+                    Constant::Other
+                }
+                15 => {
+                    reader.skip(3)?;
+                    Constant::Other
+                }
+                9 | 10 | 11 | 12 | 17 | 18 => {
+                    reader.skip(4)?;
+                    Constant::Other
+                }
+                _ => bail!("unknown constant pool tag {tag}"),
+            };
+            // longs and doubles take two slots
+            let wide = matches!(entry, Constant::Long(_) | Constant::Double(_));
+            entries.push(entry);
+            if wide {
+                entries.push(Constant::Other);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    fn utf8(&self, index: u16) -> Result<&str> {
+        match self.entries.get(index as usize) {
+            Some(Constant::Utf8(value)) => Ok(value),
+            _ => bail!("constant {index} is not a UTF-8 string"),
+        }
+    }
+
+    fn class_name(&self, index: u16) -> Result<&str> {
+        match self.entries.get(index as usize) {
+            Some(Constant::Class(name)) => self.utf8(*name),
+            _ => bail!("constant {index} is not a class"),
+        }
+    }
+
+    fn value(&self, index: u16) -> Result<String> {
+        Ok(match self.entries.get(index as usize) {
+            Some(Constant::Integer(v)) => v.to_string(),
+            Some(Constant::Float(bits)) => format!("float {bits:#x}"),
+            Some(Constant::Long(v)) => format!("{v}L"),
+            Some(Constant::Double(bits)) => format!("double {bits:#x}"),
+            Some(Constant::String(utf8)) => format!("{:?}", self.utf8(*utf8)?),
+            _ => bail!("constant {index} is not a constant value"),
+        })
+    }
+
+    /// Class names from `Class` entries and from the `Lname;` types in descriptors and signatures.
+    fn referenced_classes(&self) -> BTreeSet<String> {
+        let mut classes = BTreeSet::new();
+        for entry in &self.entries {
+            match entry {
+                Constant::Class(index) => {
+                    if let Ok(name) = self.utf8(*index) {
+                        if name.starts_with('[') {
+                            classes.extend(descriptor_classes(name));
+                        } else {
+                            classes.insert(name.to_string());
+                        }
+                    }
+                }
+                Constant::Utf8(text) => classes.extend(descriptor_classes(text)),
+                _ => {}
+            }
+        }
+        classes
+    }
+}
+
+/// `Lcom/example/Foo;` types in a descriptor or signature. Type arguments of generic
+/// signatures are cut off at `<`, and may over-approximate on arbitrary strings, which
+/// only causes extra recompilation.
+fn descriptor_classes(text: &str) -> Vec<String> {
+    let mut classes = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('L') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find([';', '<']) else {
+            break;
+        };
+        let name = &after[..end];
+        if !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '/' | '$' | '_'))
+        {
+            classes.push(name.to_string());
+        }
+        rest = &after[end..];
+    }
+    classes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let Some(slice) = self.bytes.get(self.pos..self.pos + n) else {
+            bail!("unexpected end of class file");
+        };
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u1(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u2(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u4(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u8(&mut self) -> Result<u64> {
+        Ok((u64::from(self.u4()?) << 32) | u64::from(self.u4()?))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const ACC_PUBLIC: u16 = 0x0001;
+
+    #[derive(Clone, Copy)]
+    pub struct Member {
+        pub access: u16,
+        pub name: &'static str,
+        pub descriptor: &'static str,
+        pub signature: Option<&'static str>,
+        /// `ConstantValue`, written as a long for `J` fields and as an int otherwise
+        pub value: Option<i64>,
+    }
+
+    pub fn member(access: u16, name: &'static str, descriptor: &'static str) -> Member {
+        Member {
+            access,
+            name,
+            descriptor,
+            signature: None,
+            value: None,
+        }
+    }
+
+    /// Writes the parts of a class file that `ClassFile::parse` reads.
+    pub struct ClassWriter {
+        pub name: &'static str,
+        pub interfaces: Vec<&'static str>,
+        pub fields: Vec<Member>,
+        pub methods: Vec<Member>,
+        pub source_file: Option<&'static str>,
+    }
+
+    #[derive(Default)]
+    struct Pool {
+        bytes: Vec<u8>,
+        count: u16,
+    }
+
+    impl Pool {
+        fn push(&mut self, bytes: &[u8], slots: u16) -> u16 {
+            self.bytes.extend(bytes);
+            self.count += slots;
+            self.count - slots + 1
+        }
+
+        fn utf8(&mut self, text: &str) -> u16 {
+            let mut bytes = vec![1];
+            bytes.extend((text.len() as u16).to_be_bytes());
+            bytes.extend(text.as_bytes());
+            self.push(&bytes, 1)
+        }
+
+        fn class(&mut self, name: &str) -> u16 {
+            let utf8 = self.utf8(name);
+            let mut bytes = vec![7];
+            bytes.extend(utf8.to_be_bytes());
+            self.push(&bytes, 1)
+        }
+
+        fn value(&mut self, value: i64, long: bool) -> u16 {
+            if long {
+                let mut bytes = vec![5];
+                bytes.extend(value.to_be_bytes());
+                self.push(&bytes, 2)
+            } else {
+                let mut bytes = vec![3];
+                bytes.extend((value as i32).to_be_bytes());
+                self.push(&bytes, 1)
+            }
+        }
+    }
+
+    impl ClassWriter {
+        pub fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                interfaces: vec![],
+                fields: vec![],
+                methods: vec![],
+                source_file: None,
+            }
+        }
+
+        pub fn bytes(&self) -> Vec<u8> {
+            let mut pool = Pool::default();
+            let mut body = vec![];
+            body.extend((ACC_PUBLIC | 0x0020).to_be_bytes());
+            body.extend(pool.class(self.name).to_be_bytes());
+            body.extend(pool.class("java/lang/Object").to_be_bytes());
+            body.extend((self.interfaces.len() as u16).to_be_bytes());
+            for interface in &self.interfaces {
+                body.extend(pool.class(interface).to_be_bytes());
+            }
+            for members in [&self.fields, &self.methods] {
+                body.extend((members.len() as u16).to_be_bytes());
+                for member in members {
+                    body.extend(member.access.to_be_bytes());
+                    body.extend(pool.utf8(member.name).to_be_bytes());
+                    body.extend(pool.utf8(member.descriptor).to_be_bytes());
+                    let mut attributes = vec![];
+                    if let Some(signature) = member.signature {
+                        attributes.push(("Signature", pool.utf8(signature)));
+                    }
+                    if let Some(value) = member.value {
+                        let value = pool.value(value, member.descriptor == "J");
+                        attributes.push(("ConstantValue", value));
+                    }
+                    write_attributes(&mut body, &mut pool, &attributes);
+                }
+            }
+            let mut attributes = vec![];
+            if let Some(source_file) = self.source_file {
+                attributes.push(("SourceFile", pool.utf8(source_file)));
+            }
+            write_attributes(&mut body, &mut pool, &attributes);
+
+            let mut bytes = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 61];
+            bytes.extend((pool.count + 1).to_be_bytes());
+            bytes.extend(pool.bytes);
+            bytes.extend(body);
+            bytes
+        }
+
+        fn parse(&self) -> ClassFile {
+            ClassFile::parse(&self.bytes()).unwrap()
+        }
+    }
+
+    /// Attributes whose body is a single constant pool index.
+    fn write_attributes(body: &mut Vec<u8>, pool: &mut Pool, attributes: &[(&str, u16)]) {
+        body.extend((attributes.len() as u16).to_be_bytes());
+        for (name, index) in attributes {
+            body.extend(pool.utf8(name).to_be_bytes());
+            body.extend(2u32.to_be_bytes());
+            body.extend(index.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn reads_name_source_file_and_references() {
+        let mut class = ClassWriter::new("com/example/Main$Inner");
+        class.source_file = Some("Main.java");
+        class.interfaces = vec!["java/lang/Runnable"];
+        class.fields = vec![Member {
+            signature: Some("Ljava/util/List<Lcom/example/Item;>;"),
+            ..member(ACC_PUBLIC, "items", "Ljava/util/List;")
+        }];
+        class.methods = vec![member(
+            ACC_PUBLIC,
+            "greet",
+            "(Lcom/example/Helper;[Ljava/lang/String;)V",
+        )];
+
+        let parsed = class.parse();
+        assert_eq!(parsed.name, "com/example/Main$Inner");
+        assert_eq!(parsed.package(), "com/example");
+        assert_eq!(parsed.source_file.as_deref(), Some("Main.java"));
+        let references: Vec<&str> = parsed.references.iter().map(String::as_str).collect();
+        assert_eq!(
+            references,
+            [
+                "com/example/Helper",
+                "com/example/Item",
+                "java/lang/Object",
+                "java/lang/Runnable",
+                "java/lang/String",
+                "java/util/List",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_package_is_empty() {
+        assert_eq!(ClassWriter::new("Main").parse().package(), "");
+    }
+
+    #[test]
+    fn private_and_synthetic_members_are_not_part_of_the_abi() {
+        let mut class = ClassWriter::new("Main");
+        class.methods = vec![member(ACC_PUBLIC, "run", "()V")];
+        let before = class.parse();
+
+        class.methods.push(member(ACC_PRIVATE, "helper", "()V"));
+        class
+            .fields
+            .push(member(ACC_SYNTHETIC | ACC_FINAL, "this$0", "LOuter;"));
+        assert_eq!(class.parse().abi, before.abi);
+
+        class.methods.push(member(ACC_PUBLIC, "stop", "()V"));
+        assert_ne!(class.parse().abi, before.abi);
+    }
+
+    #[test]
+    fn member_order_is_not_part_of_the_abi() {
+        let mut class = ClassWriter::new("Main");
+        class.methods = vec![
+            member(ACC_PUBLIC, "start", "()V"),
+            member(ACC_PUBLIC, "stop", "()V"),
+        ];
+        let before = class.parse();
+        class.methods.reverse();
+        assert_eq!(class.parse().abi, before.abi);
+    }
+
+    #[test]
+    fn descriptor_and_signature_changes_are_part_of_the_abi() {
+        let mut class = ClassWriter::new("Main");
+        class.methods = vec![member(ACC_PUBLIC, "size", "()I")];
+        let before = class.parse();
+
+        class.methods = vec![member(ACC_PUBLIC, "size", "()J")];
+        assert_ne!(class.parse().abi, before.abi);
+
+        class.methods = vec![Member {
+            signature: Some("()I"),
+            ..member(ACC_PUBLIC, "size", "()I")
+        }];
+        assert_ne!(class.parse().abi, before.abi);
+    }
+
+    #[test]
+    fn constant_values_are_hashed_apart_from_the_abi() {
+        let constant = |value| Member {
+            value: Some(value),
+            ..member(ACC_PUBLIC | ACC_STATIC | ACC_FINAL, "LIMIT", "J")
+        };
+        let mut class = ClassWriter::new("Main");
+        // the long takes two pool slots, so the name after it is only found if they are counted
+        class.fields = vec![
+            constant(10),
+            member(ACC_PUBLIC, "name", "Ljava/lang/String;"),
+        ];
+        let before = class.parse();
+
+        class.fields[0] = constant(20);
+        let after = class.parse();
+        assert_eq!(after.abi, before.abi);
+        assert_ne!(after.constants, before.constants);
+    }
+
+    #[test]
+    fn constant_values_of_instance_fields_are_ignored() {
+        let mut class = ClassWriter::new("Main");
+        class.fields = vec![Member {
+            value: Some(1),
+            ..member(ACC_PUBLIC | ACC_FINAL, "size", "I")
+        }];
+        let before = class.parse();
+        class.fields[0].value = Some(2);
+        assert_eq!(class.parse().constants, before.constants);
+    }
+
+    #[test]
+    fn rejects_other_and_truncated_files() {
+        let error = ClassFile::parse(b"PK\x03\x04 not a class").unwrap_err();
+        assert_eq!(error.to_string(), "not a class file");
+
+        let bytes = ClassWriter::new("Main").bytes();
+        let error = ClassFile::parse(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of class file");
+    }
+}
@@ -0,0 +1,677 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::deps::sha256_file;
+
+mod classfile;
+
+pub use classfile::ClassFile;
+
+const HEADER: &str = "# Generated by Cup to decide what to recompile. Safe to delete.\n\n";
+
+/// What was compiled from each source file, and how the resulting classes
+/// depend on each other. Stored under `build/.cup`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Fingerprints {
+    pub version: u32,
+    /// Hash of everything besides the sources that affects the classes, see `build_fingerprint`
+    #[serde(default)]
+    pub build: String,
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceFingerprint>,
+    #[serde(default)]
+    pub classes: BTreeMap<String, ClassFingerprint>,
+    /// Reverse dependency graph: each class and the classes that reference it
+    #[serde(default)]
+    pub dependents: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SourceFingerprint {
+    /// SHA-256 of the source file
+    pub hash: String,
+    /// Class files produced from it, relative to the output directory
+    pub classes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClassFingerprint {
+    pub abi: String,
+    pub constants: String,
+}
+
+/// The sources to compile first, and the ones deleted since the last build.
+#[derive(Debug)]
+pub struct Plan {
+    pub compile: Vec<PathBuf>,
+    pub removed: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_up_to_date(&self) -> bool {
+        self.compile.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Fingerprints {
+    /// Reads the store, starting from scratch if it is missing or from an older Cup.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<Self>(&content).ok())
+            .filter(|fingerprints| fingerprints.version == 2)
+            .unwrap_or_default()
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        self.version = 2;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create build cache directory")?;
+        }
+        let content = toml::to_string(self).context("Failed to serialize fingerprints")?;
+        fs::write(path, format!("{HEADER}{content}"))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Deletes every class from the last build and starts over.
+    pub fn reset(&mut self, output_dir: &Path) -> Result<()> {
+        let keys: Vec<String> = self.sources.keys().cloned().collect();
+        self.invalidate(keys, output_dir)?;
+        *self = Self::default();
+        Ok(())
+    }
+
+    /// New and edited sources, and sources whose classes went missing.
+    pub fn plan(&self, sources: &[PathBuf], output_dir: &Path) -> Result<Plan> {
+        let mut changed = BTreeSet::new();
+        for source in sources {
+            let key = source_key(source);
+            let hash = sha256_file(source)?;
+            let fresh = self.sources.get(&key).is_some_and(|previous| {
+                previous.hash == hash
+                    && previous
+                        .classes
+                        .iter()
+                        .all(|class| output_dir.join(class).is_file())
+            });
+            if !fresh {
+                changed.insert(key);
+            }
+        }
+        let removed: Vec<String> = self
+            .sources
+            .keys()
+            .filter(|key| !sources.iter().any(|source| source_key(source) == **key))
+            .cloned()
+            .collect();
+
+        // Kotlin inline functions are copied into their callers, which the ABI
+        // hash can't see, so any Kotlin change recompiles all Kotlin sources
+        let kotlin_changed = changed
+            .iter()
+            .chain(&removed)
+            .any(|key| key.ends_with(".kt"));
+        let compile = sources
+            .iter()
+            .filter(|source| {
+                let key = source_key(source);
+                changed.contains(&key) || (kotlin_changed && key.ends_with(".kt"))
+            })
+            .cloned()
+            .collect();
+
+        Ok(Plan { compile, removed })
+    }
+
+    /// Deletes the classes of sources about to be recompiled or removed, and forgets
+    /// them until they compile again. Returns their fingerprints from before.
+    pub fn invalidate(
+        &mut self,
+        keys: impl IntoIterator<Item = String>,
+        output_dir: &Path,
+    ) -> Result<BTreeMap<String, ClassFingerprint>> {
+        let mut previous = BTreeMap::new();
+        for key in keys {
+            let Some(source) = self.sources.remove(&key) else {
+                continue;
+            };
+            for class in source.classes {
+                let path = output_dir.join(&class);
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {}", path.display()))?;
+                }
+                let name = class_name(&class);
+                if let Some(fingerprint) = self.classes.remove(name) {
+                    previous.insert(name.to_string(), fingerprint);
+                }
+            }
+        }
+        Ok(previous)
+    }
+
+    /// Assigns the class files that appeared in `output_dir` to the sources that were
+    /// just compiled, using each class's `SourceFile` attribute and package, and records
+    /// their fingerprints and references. Returns the names of the recorded classes.
+    pub fn record(&mut self, compiled: &[PathBuf], output_dir: &Path) -> Result<Vec<String>> {
+        let owned: BTreeSet<&String> = self.sources.values().flat_map(|s| &s.classes).collect();
+        let mut produced: BTreeMap<String, Vec<String>> = compiled
+            .iter()
+            .map(|source| (source_key(source), vec![]))
+            .collect();
+        let mut class_files_read = vec![];
+
+        for class in class_files(output_dir)? {
+            let relative = class_key(&class, output_dir);
+            if owned.contains(&relative) {
+                continue;
+            }
+            let Ok(class_file) = ClassFile::read(&class) else {
+                continue;
+            };
+            let Some(source_file) = &class_file.source_file else {
+                continue;
+            };
+            let candidates: Vec<&PathBuf> = compiled
+                .iter()
+                .filter(|source| {
+                    source
+                        .file_name()
+                        .is_some_and(|n| n == source_file.as_str())
+                })
+                .collect();
+            // Prefer the source whose directory matches the package
+            let in_package = format!("{}/{source_file}", class_file.package());
+            let source = candidates
+                .iter()
+                .find(|source| source_key(source).ends_with(&in_package))
+                .or(candidates.first());
+            if let Some(source) = source
+                && let Some(classes) = produced.get_mut(&source_key(source))
+            {
+                classes.push(relative);
+                class_files_read.push(class_file);
+            }
+        }
+
+        for class in &class_files_read {
+            self.classes.insert(
+                class.name.clone(),
+                ClassFingerprint {
+                    abi: class.abi.clone(),
+                    constants: class.constants.clone(),
+                },
+            );
+        }
+        // Replace the edges out of the recompiled classes
+        for users in self.dependents.values_mut() {
+            for class in &class_files_read {
+                users.remove(&class.name);
+            }
+        }
+        for class in &class_files_read {
+            for reference in &class.references {
+                if self.classes.contains_key(reference) {
+                    self.dependents
+                        .entry(reference.clone())
+                        .or_default()
+                        .insert(class.name.clone());
+                }
+            }
+        }
+
+        for (key, classes) in produced {
+            let hash = sha256_file(Path::new(&key))?;
+            self.sources
+                .insert(key, SourceFingerprint { hash, classes });
+        }
+        Ok(class_files_read.into_iter().map(|c| c.name).collect())
+    }
+
+    /// Sources that have to be recompiled because classes they use changed their ABI or
+    /// disappeared. Constants are inlined by javac without a reference to their class, so
+    /// for changed constants every source mentioning the class by name is recompiled.
+    pub fn affected(
+        &self,
+        previous: &BTreeMap<String, ClassFingerprint>,
+        recorded: &[String],
+        sources: &[PathBuf],
+        done: &BTreeSet<String>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut abi_changed: BTreeSet<&str> = BTreeSet::new();
+        let mut constants_changed: BTreeSet<&str> = BTreeSet::new();
+        for name in recorded {
+            let current = &self.classes[name];
+            match previous.get(name) {
+                Some(before) => {
+                    if before.abi != current.abi {
+                        abi_changed.insert(name);
+                    }
+                    if before.constants != current.constants {
+                        constants_changed.insert(name);
+                    }
+                }
+                // Not known from the last build, e.g. after a failed compile
+                None => {
+                    abi_changed.insert(name);
+                    constants_changed.insert(name);
+                }
+            }
+        }
+        for name in previous.keys() {
+            if !self.classes.contains_key(name) {
+                abi_changed.insert(name);
+                constants_changed.insert(name);
+            }
+        }
+
+        let owners: BTreeMap<&str, &String> = self
+            .sources
+            .iter()
+            .flat_map(|(key, source)| source.classes.iter().map(move |c| (class_name(c), key)))
+            .collect();
+        let mut affected: BTreeSet<String> = abi_changed
+            .iter()
+            .filter_map(|name| self.dependents.get(*name))
+            .flatten()
+            .filter_map(|user| owners.get(user.as_str()))
+            .map(|key| key.to_string())
+            .collect();
+
+        let names: BTreeSet<&str> = constants_changed
+            .iter()
+            .map(|name| simple_name(name))
+            .collect();
+        if !names.is_empty() {
+            for source in sources {
+                let key = source_key(source);
+                if done.contains(&key) || affected.contains(&key) {
+                    continue;
+                }
+                let text = fs::read_to_string(source)
+                    .with_context(|| format!("Failed to read {}", source.display()))?;
+                if names.iter().any(|name| mentions(&text, name)) {
+                    affected.insert(key);
+                }
+            }
+        }
+
+        Ok(sources
+            .iter()
+            .filter(|source| {
+                let key = source_key(source);
+                affected.contains(&key) && !done.contains(&key)
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Drops graph entries for classes that no longer exist.
+    pub fn prune(&mut self) {
+        let classes = &self.classes;
+        self.dependents.retain(|class, users| {
+            users.retain(|user| classes.contains_key(user));
+            classes.contains_key(class) && !users.is_empty()
+        });
+    }
+}
+
+/// Key of a source file in the store: its path with `/` separators.
+pub fn source_key(source: &Path) -> String {
+    source.to_string_lossy().replace('\\', "/")
+}
+
+/// `com/example/Main$Inner.class` → `com/example/Main$Inner`
+fn class_name(class: &str) -> &str {
+    class.trim_end_matches(".class")
+}
+
+/// `com/example/Main$Inner` → `Inner`
+fn simple_name(class: &str) -> &str {
+    class
+        .rsplit(['/', '$'])
+        .find(|part| !part.is_empty() && !part.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(class)
+}
+
+fn class_key(class: &Path, output_dir: &Path) -> String {
+    class
+        .strip_prefix(output_dir)
+        .unwrap_or(class)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Whether `name` occurs in `text` as a whole identifier.
+fn mentions(text: &str, name: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    text.match_indices(name).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + name.len()..].chars().next();
+        !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+    })
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Cheap change marker for a classpath entry: size and modification time of a jar,
+/// or of every file in a class directory.
+pub fn path_stamp(path: &Path) -> String {
+    let stamp = |path: &Path| {
+        fs::metadata(path)
+            .map(|meta| {
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |since| since.as_nanos());
+                format!("{}@{modified}", meta.len())
+            })
+            .unwrap_or_else(|_| "missing".to_string())
+    };
+    if !path.is_dir() {
+        return stamp(path);
+    }
+    let files = files_under(path).unwrap_or_default();
+    let stamps: Vec<String> = files
+        .iter()
+        .map(|file| format!("{} {}", file.display(), stamp(file)))
+        .collect();
+    sha256_hex(stamps.join("\n").as_bytes())
+}
+
+/// Every file under `dir`, sorted.
+pub fn files_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if dir.is_dir() {
+        collect_files(dir, &mut files)
+            .with_context(|| format!("Failed to read {}", dir.display()))?;
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, acc: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, acc)?;
+        } else {
+            acc.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Every `.class` file under `dir`.
+pub fn class_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut classes = vec![];
+    if dir.is_dir() {
+        collect_class_files(dir, &mut classes)?;
+    }
+    Ok(classes)
+}
+
+fn collect_class_files(dir: &Path, acc: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_class_files(&path, acc)?;
+        } else if path.extension().is_some_and(|ext| ext == "class") {
+            acc.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classfile::tests::{ACC_PUBLIC, ClassWriter, Member, member};
+    use super::*;
+
+    const ACC_PRIVATE: u16 = 0x0002;
+    const ACC_STATIC: u16 = 0x0008;
+    const ACC_FINAL: u16 = 0x0010;
+
+    /// Sources and an output directory in a temporary directory, removed again on drop.
+    struct TestBuild {
+        root: PathBuf,
+        output_dir: PathBuf,
+    }
+
+    impl TestBuild {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("cup-incremental-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let output_dir = root.join("classes");
+            fs::create_dir_all(&output_dir).unwrap();
+            Self { root, output_dir }
+        }
+
+        fn source(&self, path: &str, text: &str) -> PathBuf {
+            let path = self.root.join("src").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, text).unwrap();
+            path
+        }
+
+        /// Writes a class file into the output directory, as if javac compiled it.
+        fn class(&self, class: &ClassWriter) {
+            let path = self.output_dir.join(format!("{}.class", class.name));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, class.bytes()).unwrap();
+        }
+    }
+
+    impl Drop for TestBuild {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn class(name: &'static str, source_file: &'static str) -> ClassWriter {
+        let mut class = ClassWriter::new(name);
+        class.source_file = Some(source_file);
+        class
+    }
+
+    /// `Main`, whose `run` method takes a `Helper`.
+    fn main_using_helper() -> ClassWriter {
+        let mut main = class("app/Main", "Main.java");
+        main.methods = vec![member(ACC_PUBLIC, "run", "(Lapp/Helper;)V")];
+        main
+    }
+
+    fn names(sources: &[PathBuf]) -> Vec<String> {
+        sources
+            .iter()
+            .map(|source| source.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn plans_edited_and_deleted_sources_and_missing_classes() {
+        let build = TestBuild::new("plan");
+        let main = build.source("app/Main.java", "class Main {}");
+        let helper = build.source("app/Helper.java", "class Helper {}");
+        let old = build.source("app/Old.java", "class Old {}");
+        let sources = vec![main.clone(), helper.clone(), old.clone()];
+        build.class(&main_using_helper());
+        build.class(&class("app/Helper", "Helper.java"));
+        build.class(&class("app/Old", "Old.java"));
+
+        let mut fingerprints = Fingerprints::default();
+        let plan = fingerprints.plan(&sources, &build.output_dir).unwrap();
+        assert_eq!(
+            names(&plan.compile),
+            ["Main.java", "Helper.java", "Old.java"]
+        );
+        fingerprints.record(&sources, &build.output_dir).unwrap();
+        assert!(
+            fingerprints
+                .plan(&sources, &build.output_dir)
+                .unwrap()
+                .is_up_to_date()
+        );
+
+        fs::write(&main, "class Main { int edited; }").unwrap();
+        fs::remove_file(build.output_dir.join("app/Helper.class")).unwrap();
+        let plan = fingerprints
+            .plan(&[main.clone(), helper.clone()], &build.output_dir)
+            .unwrap();
+        assert_eq!(names(&plan.compile), ["Main.java", "Helper.java"]);
+        assert_eq!(plan.removed, [source_key(&old)]);
+    }
+
+    #[test]
+    fn any_kotlin_change_recompiles_all_kotlin_sources() {
+        let build = TestBuild::new("kotlin");
+        let main = build.source("app/Main.kt", "fun main() = greet()");
+        let inline = build.source("app/Inline.kt", "inline fun greet() = println()");
+        let java = build.source("app/Helper.java", "class Helper {}");
+        let sources = vec![main.clone(), inline.clone(), java.clone()];
+        let mut fingerprints = Fingerprints::default();
+        fingerprints.record(&sources, &build.output_dir).unwrap();
+
+        fs::write(&inline, "inline fun greet() = print(\"hi\")").unwrap();
+        let plan = fingerprints.plan(&sources, &build.output_dir).unwrap();
+        assert_eq!(names(&plan.compile), ["Main.kt", "Inline.kt"]);
+    }
+
+    #[test]
+    fn records_classes_with_the_source_of_their_package() {
+        let build = TestBuild::new("record");
+        let app = build.source("app/Main.java", "class Main {}");
+        let tool = build.source("tool/Main.java", "class Main {}");
+        build.class(&main_using_helper());
+        build.class(&class("app/Main$Inner", "Main.java"));
+        build.class(&class("tool/Main", "Main.java"));
+        build.class(&class("app/Helper", "Helper.java"));
+
+        let mut fingerprints = Fingerprints::default();
+        let recorded = fingerprints
+            .record(&[app.clone(), tool.clone()], &build.output_dir)
+            .unwrap();
+        assert_eq!(recorded.len(), 3);
+        let mut classes = fingerprints.sources[&source_key(&app)].classes.clone();
+        classes.sort();
+        assert_eq!(classes, ["app/Main$Inner.class", "app/Main.class"]);
+        assert_eq!(
+            fingerprints.sources[&source_key(&tool)].classes,
+            ["tool/Main.class"]
+        );
+        // Helper.java was not compiled, so its class is not recorded or depended on
+        assert!(!fingerprints.classes.contains_key("app/Helper"));
+        assert!(fingerprints.dependents.is_empty());
+    }
+
+    /// Records `Main` and `Helper`, then recompiles `Helper.java` into `helper` and
+    /// returns the sources that have to be recompiled because of it.
+    fn recompile_helper(build: &TestBuild, main: &str, helper: ClassWriter) -> Vec<String> {
+        let main = build.source("app/Main.java", main);
+        let source = build.source("app/Helper.java", "class Helper {}");
+        let other = build.source("app/Other.java", "class Other {}");
+        let sources = vec![main, source.clone(), other];
+        build.class(&main_using_helper());
+        build.class(&class("app/Helper", "Helper.java"));
+        build.class(&class("app/Other", "Other.java"));
+        let mut fingerprints = Fingerprints::default();
+        fingerprints.record(&sources, &build.output_dir).unwrap();
+        assert_eq!(
+            fingerprints.dependents["app/Helper"],
+            BTreeSet::from(["app/Main".to_string()])
+        );
+
+        let key = source_key(&source);
+        let previous = fingerprints
+            .invalidate([key.clone()], &build.output_dir)
+            .unwrap();
+        assert!(!build.output_dir.join("app/Helper.class").exists());
+        build.class(&helper);
+        let recorded = fingerprints
+            .record(std::slice::from_ref(&source), &build.output_dir)
+            .unwrap();
+        let affected = fingerprints
+            .affected(&previous, &recorded, &sources, &BTreeSet::from([key]))
+            .unwrap();
+        names(&affected)
+    }
+
+    #[test]
+    fn abi_changes_recompile_the_dependents() {
+        let build = TestBuild::new("abi");
+        let mut helper = class("app/Helper", "Helper.java");
+        helper.methods = vec![member(ACC_PUBLIC, "help", "()V")];
+        assert_eq!(
+            recompile_helper(&build, "class Main {}", helper),
+            ["Main.java"]
+        );
+    }
+
+    #[test]
+    fn private_changes_recompile_nothing_else() {
+        let build = TestBuild::new("private");
+        let mut helper = class("app/Helper", "Helper.java");
+        helper.methods = vec![member(ACC_PRIVATE, "help", "()V")];
+        assert!(recompile_helper(&build, "class Main {}", helper).is_empty());
+    }
+
+    #[test]
+    fn changed_constants_recompile_the_sources_mentioning_the_class() {
+        let build = TestBuild::new("constants");
+        let mut helper = class("app/Helper", "Helper.java");
+        helper.fields = vec![Member {
+            value: Some(2),
+            ..member(ACC_PUBLIC | ACC_STATIC | ACC_FINAL, "LIMIT", "I")
+        }];
+        // javac inlines `Helper.LIMIT`, so only the source text shows the use
+        let main = "class Main { int limit = Helper.LIMIT; }";
+        let affected = recompile_helper(&build, main, helper);
+        assert_eq!(affected, ["Main.java"]);
+    }
+
+    #[test]
+    fn deleted_sources_recompile_their_users_and_prune_their_classes() {
+        let build = TestBuild::new("deleted");
+        let main = build.source("app/Main.java", "class Main {}");
+        let helper = build.source("app/Helper.java", "class Helper {}");
+        build.class(&main_using_helper());
+        build.class(&class("app/Helper", "Helper.java"));
+        let mut fingerprints = Fingerprints::default();
+        fingerprints
+            .record(&[main.clone(), helper.clone()], &build.output_dir)
+            .unwrap();
+
+        fs::remove_file(&helper).unwrap();
+        let sources = vec![main];
+        let plan = fingerprints.plan(&sources, &build.output_dir).unwrap();
+        assert!(plan.compile.is_empty());
+        let previous = fingerprints
+            .invalidate(plan.removed, &build.output_dir)
+            .unwrap();
+        assert!(!build.output_dir.join("app/Helper.class").exists());
+        assert!(!fingerprints.sources.contains_key(&source_key(&helper)));
+
+        let affected = fingerprints
+            .affected(&previous, &[], &sources, &BTreeSet::new())
+            .unwrap();
+        assert_eq!(names(&affected), ["Main.java"]);
+
+        assert!(fingerprints.dependents.contains_key("app/Helper"));
+        fingerprints.prune();
+        assert!(fingerprints.dependents.is_empty());
+    }
+}
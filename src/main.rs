@@ -1,28 +1,94 @@
+use std::{process::ExitCode, time::Instant};
+
 use clap::Parser;
 
 use cli::Cli;
-use commands::{new::new_project, run::run_project};
+use commands::{new::new_project, run::run_project, test::test_project};
 
-use crate::commands::{build::compile_project, doc::create_documentation};
+use crate::commands::{
+    RunConfig,
+    add::add_dependency,
+    build::compile_project,
+    doc::create_documentation,
+    remove::remove_dependency,
+    toolchain::{add_toolchain, list_toolchains, set_default_toolchain},
+    tree::print_tree,
+};
+use crate::deps::ResolveOptions;
 
 mod cli;
 mod commands;
+mod deps;
+mod diagnostics;
+mod failure;
+mod incremental;
+mod report;
+mod toolchain;
 
-fn main() {
+fn main() -> ExitCode {
     let cli = Cli::parse();
-    match cli.command {
+    report::init(cli.message_format);
+    let options = ResolveOptions {
+        locked: cli.locked,
+        offline: cli.offline,
+    };
+    let started = Instant::now();
+    let result = match cli.command {
         cli::Commands::New {
             project_name,
             kotlin,
         } => new_project(project_name, None, kotlin),
-        cli::Commands::Run {} => {
-            let _ = run_project().inspect_err(|e| eprintln!("{e}"));
+        cli::Commands::Run {
+            profile,
+            jvm_args,
+            system_properties,
+            env,
+            working_dir,
+            args,
+        } => {
+            let overrides = RunConfig {
+                jvm_args: Some(jvm_args),
+                system_properties: Some(system_properties.into_iter().collect()),
+                env: Some(env.into_iter().collect()),
+                working_dir,
+            };
+            let result = run_project(&profile, &args, overrides, &options);
+            report::finished(started, &result);
+            result
+        }
+        cli::Commands::Build { profile } => {
+            let result = compile_project(&profile, &options);
+            report::finished(started, &result);
+            result
         }
-        cli::Commands::Build {} => {
-            let _ = compile_project().inspect_err(|e| eprintln!("{e}"));
+        cli::Commands::Test { profile } => {
+            let result = test_project(&profile, &options);
+            report::finished(started, &result);
+            result
         }
-        cli::Commands::Doc {} => {
-            let _ = create_documentation().inspect_err(|e| eprintln!("{e}"));
+        cli::Commands::Doc {} => create_documentation(),
+        cli::Commands::Add {
+            coordinates,
+            name,
+            dev,
+            scope,
+        } => add_dependency(coordinates, name, dev, scope, &options),
+        cli::Commands::Tree { invert } => print_tree(invert, &options),
+        cli::Commands::Remove { name } => remove_dependency(name, &options),
+        cli::Commands::Toolchain { command } => match command {
+            cli::ToolchainCommands::Add { archive } => add_toolchain(archive),
+            cli::ToolchainCommands::List {} => list_toolchains(),
+            cli::ToolchainCommands::Default { version, kotlin } => {
+                set_default_toolchain(version, kotlin)
+            }
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::from(failure::exit_code(&e))
         }
     }
 }
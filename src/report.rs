@@ -0,0 +1,105 @@
+use std::{fmt::Display, path::Path, process::Command, sync::OnceLock, time::Instant};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::diagnostics::{Diagnostic, Summary};
+
+/// How `build`, `run` and `test` report what they do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Progress banners and colored diagnostics
+    #[default]
+    Human,
+    /// One JSON event per line on stdout, progress on stderr
+    Json,
+}
+
+static FORMAT: OnceLock<MessageFormat> = OnceLock::new();
+
+/// Set for the `cup` processes Cup starts itself, whose events are part of the parent's stream.
+const NESTED_ENV: &str = "CUP_NESTED_EVENTS";
+
+pub fn init(format: MessageFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&MessageFormat::Json)
+}
+
+/// Makes a `cup` process started by this one report in the same format. Its events
+/// go into this command's stream, without a `build-started` and `finished` of their own.
+pub fn forward_to(cmd: &mut Command) {
+    if is_json() {
+        cmd.args(["--message-format", "json"]).env(NESTED_ENV, "1");
+    }
+}
+
+/// Prints a progress line. With JSON output it goes to stderr, so stdout only has events.
+pub fn status(message: impl Display) {
+    if is_json() {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// An event of `--message-format json`, tagged with `"event": "<kind>"`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    BuildStarted {
+        command: &'a str,
+        project: &'a str,
+        version: &'a str,
+        profile: &'a str,
+    },
+    FileCompiled {
+        file: &'a Path,
+    },
+    Diagnostic(&'a Diagnostic),
+    ArtifactProduced {
+        /// `classes`, `test-classes` or `resources`
+        kind: &'a str,
+        path: &'a Path,
+        /// Files written by this build, relative to `path`
+        files: Vec<String>,
+        /// Nothing had to be rebuilt
+        fresh: bool,
+    },
+    Finished {
+        success: bool,
+        duration_ms: u64,
+        errors: usize,
+        warnings: usize,
+        message: Option<String>,
+    },
+}
+
+/// Writes the event to stdout when `--message-format json` is on.
+pub fn emit(event: Event) {
+    if !is_json() {
+        return;
+    }
+    let nested = std::env::var_os(NESTED_ENV).is_some();
+    if nested && matches!(event, Event::BuildStarted { .. } | Event::Finished { .. }) {
+        return;
+    }
+    if let Ok(line) = serde_json::to_string(&event) {
+        println!("{line}");
+    }
+}
+
+/// Emits the closing `finished` event of a command that started at `started`.
+pub fn finished(started: Instant, result: &Result<()>) {
+    let summary = Summary::total();
+    emit(Event::Finished {
+        success: result.is_ok(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        errors: summary.errors,
+        warnings: summary.warnings,
+        message: result.as_ref().err().map(|e| format!("{e:#}")),
+    });
+}
@@ -0,0 +1,540 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::commands::{CupConfig, ToolchainConfig, cup_home};
+use crate::deps::expand_home;
+use crate::incremental::path_stamp;
+
+/// An installed JDK or Kotlin compiler.
+#[derive(Debug, Clone)]
+pub struct Installation {
+    pub home: PathBuf,
+    pub version: String,
+    /// `IMPLEMENTOR` from the JDK's `release` file
+    pub vendor: Option<String>,
+}
+
+impl fmt::Display for Installation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.version)?;
+        if let Some(vendor) = &self.vendor {
+            write!(f, " ({vendor})")?;
+        }
+        write!(f, " at {}", self.home.display())
+    }
+}
+
+/// The JDK and Kotlin compiler a project builds with.
+/// `None` means the tool is looked up through PATH.
+#[derive(Debug, Default)]
+pub struct Toolchain {
+    pub jdk: Option<Installation>,
+    pub kotlin: Option<Installation>,
+}
+
+impl Toolchain {
+    /// Picks the JDK from `[toolchain]` or `java_version`, and the Kotlin compiler from `[toolchain]`.
+    /// Without a JDK in `[toolchain]`, the default toolchain is used if it can target `java_version`.
+    pub fn select(config: &CupConfig) -> Result<Self> {
+        let mut toolchain = config.toolchain.clone().unwrap_or_default();
+        let release = match config.build.as_ref().and_then(|b| b.java_version.as_ref()) {
+            Some(java_version) => {
+                Some(parse_java_version(java_version).with_context(|| {
+                    format!("invalid java_version '{java_version}' in Cup.toml")
+                })?)
+            }
+            None => None,
+        };
+
+        let defaults = load_defaults()?;
+        let jdks = discover_jdks();
+        let mut source = "[toolchain]";
+        let mut default_jdk = None;
+        if toolchain.java.is_none() && toolchain.vendor.is_none() {
+            match release {
+                // Projects that don't ask for a JDK get the one set with `cup toolchain default`
+                None => {
+                    toolchain.java = defaults.java;
+                    toolchain.vendor = defaults.vendor;
+                    source = "the default toolchain";
+                }
+                // and so do the ones that only set java_version, if it is new enough
+                Some(release) => {
+                    default_jdk = find_default_jdk(&jdks, &defaults)
+                        .filter(|jdk| {
+                            parse_java_version(&jdk.version).is_some_and(|v| v >= release)
+                        })
+                        .cloned();
+                }
+            }
+        }
+
+        let jdk = match (default_jdk, &toolchain.java, release) {
+            (Some(jdk), _, _) => Some(jdk),
+            (None, Some(wanted), _) => match find_jdk(&jdks, wanted, toolchain.vendor.as_deref()) {
+                Some(jdk) => Some(jdk.clone()),
+                None => bail!(
+                    "No installed JDK matches java = \"{wanted}\"{} from {source}.\n{}",
+                    vendor_note(toolchain.vendor.as_deref()),
+                    installed_list("JDKs", &jdks)
+                ),
+            },
+            // Prefer the exact release, then the oldest JDK that can still target it
+            (None, None, Some(release)) => {
+                let mut candidates: Vec<&Installation> = jdks
+                    .iter()
+                    .filter(|jdk| vendor_matches(jdk, toolchain.vendor.as_deref()))
+                    .filter(|jdk| parse_java_version(&jdk.version).is_some_and(|v| v >= release))
+                    .collect();
+                candidates.sort_by_key(|jdk| parse_java_version(&jdk.version));
+                let exact: Vec<&Installation> = candidates
+                    .iter()
+                    .copied()
+                    .filter(|jdk| parse_java_version(&jdk.version) == Some(release))
+                    .collect();
+                newest(&exact).or(candidates.first().copied()).cloned()
+            }
+            (None, None, None) => match &toolchain.vendor {
+                Some(vendor) => {
+                    let matching: Vec<&Installation> = jdks
+                        .iter()
+                        .filter(|jdk| vendor_matches(jdk, Some(vendor)))
+                        .collect();
+                    match newest(&matching) {
+                        Some(jdk) => Some(jdk.clone()),
+                        None => bail!(
+                            "No installed JDK from vendor \"{vendor}\".\n{}",
+                            installed_list("JDKs", &jdks)
+                        ),
+                    }
+                }
+                None => java_home_jdk(),
+            },
+        };
+
+        let kotlin = match toolchain.kotlin.or(defaults.kotlin) {
+            Some(wanted) => {
+                let compilers = discover_kotlin();
+                match find_kotlin(&compilers, &wanted) {
+                    Some(kotlin) => Some(kotlin.clone()),
+                    None => bail!(
+                        "No installed Kotlin compiler matches kotlin = \"{wanted}\".\n{}",
+                        installed_list("Kotlin compilers", &compilers)
+                    ),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Self { jdk, kotlin })
+    }
+
+    pub fn javac(&self) -> Command {
+        Command::new(self.jdk_tool("javac"))
+    }
+
+    pub fn java(&self) -> Command {
+        Command::new(self.jdk_tool("java"))
+    }
+
+    pub fn javadoc(&self) -> Command {
+        Command::new(self.jdk_tool("javadoc"))
+    }
+
+    pub fn kotlinc(&self) -> Command {
+        let name = if cfg!(windows) {
+            "kotlinc.bat"
+        } else {
+            "kotlinc"
+        };
+        let mut cmd = match &self.kotlin {
+            Some(kotlin) => Command::new(kotlin.home.join("bin").join(name)),
+            None => Command::new(name),
+        };
+        // The kotlinc script starts its compiler with the java from JAVA_HOME
+        if let Some(jdk) = &self.jdk {
+            cmd.env("JAVA_HOME", &jdk.home);
+        }
+        cmd
+    }
+
+    /// Feature release of the selected JDK, e.g. 17.
+    pub fn java_release(&self) -> Result<u32> {
+        match &self.jdk {
+            Some(jdk) => parse_java_version(&jdk.version)
+                .with_context(|| format!("Unrecognized JDK version {}", jdk.version)),
+            None => path_javac_release(),
+        }
+    }
+
+    /// `kotlin-stdlib.jar` of the selected compiler, or of a Kotlin installed in a common location.
+    pub fn kotlin_stdlib(&self) -> Option<PathBuf> {
+        if let Some(kotlin) = &self.kotlin {
+            return Some(kotlin.home.join("lib").join("kotlin-stdlib.jar"));
+        }
+        [
+            "/usr/share/kotlin/lib/kotlin-stdlib.jar",
+            "/opt/kotlin/lib/kotlin-stdlib.jar",
+            "~/.kotlinc/lib/kotlin-stdlib.jar",
+            "/usr/local/share/kotlin/lib/kotlin-stdlib.jar",
+            "C:\\Program Files\\Kotlin\\lib\\kotlin-stdlib.jar",
+            "C:\\kotlin\\lib\\kotlin-stdlib.jar",
+        ]
+        .into_iter()
+        .map(expand_home)
+        .find(|path| path.exists())
+    }
+
+    /// `lib` directory of the selected compiler or of the kotlinc in PATH, with the bundled plugins.
+    pub fn kotlin_lib(&self) -> Option<PathBuf> {
+        if let Some(kotlin) = &self.kotlin {
+            return Some(kotlin.home.join("lib"));
+        }
+        let lib = find_in_path("kotlinc")
+            .and_then(|kotlinc| Some(kotlinc.parent()?.parent()?.join("lib")));
+        match lib {
+            Some(lib) if lib.is_dir() => Some(lib),
+            _ => Some(self.kotlin_stdlib()?.parent()?.to_path_buf()),
+        }
+    }
+
+    /// Identifies the JDK and Kotlin compiler, so a build notices when either is swapped.
+    pub fn fingerprint(&self) -> String {
+        let describe = |installation: &Option<Installation>, tool: &str| match installation {
+            Some(installation) => installation.to_string(),
+            None => find_in_path(tool).map_or_else(
+                || tool.to_string(),
+                |path| format!("{} {}", path.display(), path_stamp(&path)),
+            ),
+        };
+        format!(
+            "jdk {}; kotlin {}",
+            describe(&self.jdk, "javac"),
+            describe(&self.kotlin, "kotlinc")
+        )
+    }
+
+    fn jdk_tool(&self, name: &str) -> PathBuf {
+        let name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+        match &self.jdk {
+            Some(jdk) => jdk.home.join("bin").join(name),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+/// Where `cup toolchain add` unpacks JDKs and Kotlin compilers.
+pub fn toolchains_dir() -> Result<PathBuf> {
+    Ok(cup_home()?.join("toolchains"))
+}
+
+fn defaults_path() -> Result<PathBuf> {
+    Ok(toolchains_dir()?.join("default.toml"))
+}
+
+/// The toolchain set with `cup toolchain default`, used when a project doesn't pick one.
+pub fn load_defaults() -> Result<ToolchainConfig> {
+    let path = defaults_path()?;
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(ToolchainConfig::default());
+    };
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+pub fn save_defaults(defaults: &ToolchainConfig) -> Result<()> {
+    let path = defaults_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let content = toml::to_string(defaults).context("Failed to serialize default toolchain")?;
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The JDK set with `cup toolchain default`, if it is installed.
+fn find_default_jdk<'a>(
+    jdks: &'a [Installation],
+    defaults: &ToolchainConfig,
+) -> Option<&'a Installation> {
+    match (&defaults.java, &defaults.vendor) {
+        (Some(java), vendor) => find_jdk(jdks, java, vendor.as_deref()),
+        (None, Some(vendor)) => {
+            let matching: Vec<&Installation> = jdks
+                .iter()
+                .filter(|jdk| vendor_matches(jdk, Some(vendor)))
+                .collect();
+            newest(&matching)
+        }
+        (None, None) => None,
+    }
+}
+
+/// The newest JDK matching a version like `"21"` or `"21.0.2"` and, optionally, a vendor.
+pub fn find_jdk<'a>(
+    jdks: &'a [Installation],
+    java: &str,
+    vendor: Option<&str>,
+) -> Option<&'a Installation> {
+    let matching: Vec<&Installation> = jdks
+        .iter()
+        .filter(|jdk| java_matches(&jdk.version, java))
+        .filter(|jdk| vendor_matches(jdk, vendor))
+        .collect();
+    newest(&matching)
+}
+
+pub fn find_kotlin<'a>(compilers: &'a [Installation], version: &str) -> Option<&'a Installation> {
+    let matching: Vec<&Installation> = compilers
+        .iter()
+        .filter(|k| version_matches(&k.version, version))
+        .collect();
+    newest(&matching)
+}
+
+/// JDKs from `JAVA_HOME`, the system JVM directories, SDKMAN, asdf and `~/.cup/toolchains`.
+pub fn discover_jdks() -> Vec<Installation> {
+    let mut homes = vec![];
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        homes.push(PathBuf::from(java_home));
+    }
+    homes.extend(subdirectories(Path::new("/usr/lib/jvm")));
+    homes.extend(
+        subdirectories(Path::new("/Library/Java/JavaVirtualMachines"))
+            .into_iter()
+            .map(|dir| dir.join("Contents").join("Home")),
+    );
+    homes.extend(subdirectories(&expand_home("~/.sdkman/candidates/java")));
+    homes.extend(subdirectories(&expand_home("~/.asdf/installs/java")));
+    homes.extend(
+        installed_toolchains()
+            .into_iter()
+            .flat_map(|dir| [dir.join("Contents").join("Home"), dir]),
+    );
+
+    let mut jdks: Vec<Installation> = vec![];
+    for home in homes {
+        let Ok(home) = home.canonicalize() else {
+            continue;
+        };
+        if jdks.iter().any(|jdk| jdk.home == home) {
+            continue;
+        }
+        if let Some(jdk) = read_jdk(&home) {
+            jdks.push(jdk);
+        }
+    }
+    jdks
+}
+
+/// Kotlin compilers from `KOTLIN_HOME`, SDKMAN, asdf and `~/.cup/toolchains`.
+pub fn discover_kotlin() -> Vec<Installation> {
+    let mut homes = vec![];
+    if let Some(kotlin_home) = std::env::var_os("KOTLIN_HOME") {
+        homes.push(PathBuf::from(kotlin_home));
+    }
+    homes.extend(subdirectories(&expand_home("~/.sdkman/candidates/kotlin")));
+    // asdf unpacks the distribution into a nested `kotlinc` directory
+    homes.extend(
+        subdirectories(&expand_home("~/.asdf/installs/kotlin"))
+            .into_iter()
+            .flat_map(|dir| [dir.join("kotlinc"), dir]),
+    );
+    homes.extend(installed_toolchains());
+
+    let mut compilers: Vec<Installation> = vec![];
+    for home in homes {
+        let Ok(home) = home.canonicalize() else {
+            continue;
+        };
+        if compilers.iter().any(|k| k.home == home) {
+            continue;
+        }
+        if let Some(kotlin) = read_kotlin(&home) {
+            compilers.push(kotlin);
+        }
+    }
+    compilers
+}
+
+/// Reads a JDK's `release` file. JREs without `javac` are skipped.
+pub fn read_jdk(home: &Path) -> Option<Installation> {
+    let javac = format!("javac{}", std::env::consts::EXE_SUFFIX);
+    if !home.join("bin").join(javac).is_file() {
+        return None;
+    }
+    let release = fs::read_to_string(home.join("release")).ok()?;
+    let property = |key: &str| {
+        release.lines().find_map(|line| {
+            let value = line.strip_prefix(key)?.strip_prefix('=')?;
+            Some(value.trim().trim_matches('"').to_string())
+        })
+    };
+    Some(Installation {
+        home: home.to_path_buf(),
+        version: property("JAVA_VERSION")?,
+        vendor: property("IMPLEMENTOR"),
+    })
+}
+
+/// Reads the version of a Kotlin compiler distribution from its `build.txt`.
+pub fn read_kotlin(home: &Path) -> Option<Installation> {
+    if !home.join("lib").join("kotlin-compiler.jar").is_file() {
+        return None;
+    }
+    let build = fs::read_to_string(home.join("build.txt")).ok()?;
+    // e.g. `2.0.21-release-482`
+    let version = build.trim().split("-release").next()?.to_string();
+    Some(Installation {
+        home: home.to_path_buf(),
+        version,
+        vendor: None,
+    })
+}
+
+/// Feature release of a Java version string: `"17"`, `"17.0.2"` and `"1.8.0_292"` give 17, 17 and 8.
+pub fn parse_java_version(version: &str) -> Option<u32> {
+    let version = version.trim().trim_matches('"');
+    let version = version.strip_prefix("1.").unwrap_or(version);
+    let digits: String = version.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// `"21"` matches every 21.x, `"21.0.2"` only that update. `"1.8"` and `"8"` are the same.
+fn java_matches(version: &str, wanted: &str) -> bool {
+    let version = version.strip_prefix("1.").unwrap_or(version);
+    let wanted = wanted.trim();
+    let wanted = wanted.strip_prefix("1.").unwrap_or(wanted);
+    version == wanted
+        || version
+            .strip_prefix(wanted)
+            .is_some_and(|rest| rest.starts_with(['.', '_', '+']))
+}
+
+fn version_matches(version: &str, wanted: &str) -> bool {
+    let wanted = wanted.trim();
+    version == wanted
+        || version
+            .strip_prefix(wanted)
+            .is_some_and(|rest| rest.starts_with(['.', '-']))
+}
+
+fn vendor_matches(jdk: &Installation, vendor: Option<&str>) -> bool {
+    match vendor {
+        Some(vendor) => jdk
+            .vendor
+            .as_ref()
+            .is_some_and(|v| v.to_lowercase().contains(&vendor.to_lowercase())),
+        None => true,
+    }
+}
+
+fn vendor_note(vendor: Option<&str>) -> String {
+    vendor
+        .map(|v| format!(" from vendor \"{v}\""))
+        .unwrap_or_default()
+}
+
+/// The highest version among `installations`, keeping the first one found on ties.
+fn newest<'a>(installations: &[&'a Installation]) -> Option<&'a Installation> {
+    installations.iter().copied().reduce(|best, next| {
+        if version_key(&next.version) > version_key(&best.version) {
+            next
+        } else {
+            best
+        }
+    })
+}
+
+fn version_key(version: &str) -> Vec<u32> {
+    let version = version.strip_prefix("1.").unwrap_or(version);
+    version
+        .split(['.', '_', '+', '-'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+fn installed_list(what: &str, installations: &[Installation]) -> String {
+    if installations.is_empty() {
+        return format!("No {what} were found.");
+    }
+    let mut list = format!("Installed {what}:");
+    for installation in installations {
+        list.push_str(&format!("\n  {installation}"));
+    }
+    list
+}
+
+fn java_home_jdk() -> Option<Installation> {
+    let java_home = std::env::var_os("JAVA_HOME")?;
+    read_jdk(&PathBuf::from(java_home).canonicalize().ok()?)
+}
+
+/// Major version of the `javac` in PATH, from `javac -version`.
+fn path_javac_release() -> Result<u32> {
+    static RELEASE: OnceLock<u32> = OnceLock::new();
+    if let Some(release) = RELEASE.get() {
+        return Ok(*release);
+    }
+
+    let output = Command::new("javac")
+        .arg("-version")
+        .output()
+        .context("Failed to execute javac. Make sure Java is installed and in PATH.")?;
+    // JDK 8 prints the version on stderr, newer JDKs on stdout
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let release = text
+        .split_whitespace()
+        .skip_while(|word| *word != "javac")
+        .nth(1)
+        .and_then(parse_java_version)
+        .with_context(|| format!("Could not read the JDK version from `javac -version`: {text}"))?;
+
+    Ok(*RELEASE.get_or_init(|| release))
+}
+
+/// Resolves `name` through PATH, following symlinks to the actual installation.
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+        .and_then(|path| path.canonicalize().ok())
+}
+
+fn installed_toolchains() -> Vec<PathBuf> {
+    let Ok(dir) = toolchains_dir() else {
+        return vec![];
+    };
+    // skips the staging directories of `cup toolchain add`
+    subdirectories(&dir)
+        .into_iter()
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        })
+        .collect()
+}
+
+pub fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}